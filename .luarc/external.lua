@@ -12,6 +12,12 @@ function log(text) end
 
 function play_pause() end
 
+function volume_up() end
+
+function volume_down() end
+
+function mute() end
+
 function next_song() end
 
 function prev_song() end
@@ -22,10 +28,23 @@ function prev_subtune() end
 
 function sub_song(n) end
 
+---@param secs number Seconds to seek, negative to seek backwards
+function seek(secs) end
+
+---@param secs number Position in seconds from start of song
+function seek_to(secs) end
+
 function goto_parent() end
 
 function show_favorites() end
 
+function show_playlists() end
+
+---@param name string
+function new_playlist(name) end
+
+function remove_from_playlist() end
+
 function show_directory() end
 
 function show_main() end
@@ -45,3 +64,46 @@ function add_char(c) end
 function show_current() end
 
 function enter_or_play_selected() end
+
+function cycle_play_mode() end
+
+function show_queue() end
+
+function show_history() end
+
+---Add a song to the end of the play queue
+function enqueue(song) end
+
+---Add a song first in the play queue
+function enqueue_next(song) end
+
+---Remove the selected song from the play queue (when the queue is shown)
+function dequeue_selected() end
+
+---@param delta integer Steps to move the selected song, negative to move it up
+function move_queued(delta) end
+
+function clear_queue() end
+
+---@return table songs The songs in the play queue
+function get_queue() end
+
+---Save a song list as an M3U playlist
+---@param file string
+---@param what? "playlist"|"search"|"favorites" Defaults to the current playlist
+function export_m3u(file, what) end
+
+---@param mode "normal"|"repeat_one"|"repeat_all"|"shuffle"|"shuffle_all"
+function set_play_mode(mode) end
+
+---@return string
+function get_play_mode() end
+
+---Sort the current song list by the next sort order
+function cycle_sort_order() end
+
+---@param order "default"|"title"|"composer"|"format"|"size"|"length"|"added"|"play_count"
+function set_sort_order(order) end
+
+---@return string order The sort order of the current song list
+function get_sort_order() end
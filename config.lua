@@ -35,6 +35,11 @@ local function title_and_composer(meta)
   return title .. composer
 end
 
+local function volume(meta)
+  if not meta.volume or meta.volume == "" then return "" end
+  return "VOL: " .. meta.volume .. "%"
+end
+
 local template = [[
  ┏━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━$>━┳━━━━━━┓
  ┃ $title_and_composer                             $> ┃SIZE: ┃
@@ -43,7 +48,7 @@ local template = [[
  ┃ $time    / $len  ┃ SONG ┃ $a/$b ┃ FORMAT ┃ $fmt $>  $count┃
  ┗━━━━━━━━━━━━━━━━━━┻━━━━━━┻━━━━━━━┻━━━━━━━━┻━━━━━━$>━━━━━━━━┛
   NEXT: $next_song
-
+ $vol       $play_mode
 $search
 
  $fft
@@ -62,6 +67,8 @@ local vars = {
   title_and_composer = { func = title_and_composer },
   hs = { func = human_size },
   count = { color = 0x808080 },
+  play_mode = { color = 0x40c0ff },
+  vol = { func = volume, color = 0x808080 },
 }
 local keys
 if true then
@@ -72,10 +79,16 @@ if true then
     end },
     { "n", "Left",  prev_subtune },
     { "n", "Right", next_subtune },
+    { "n", "shift-Right", function() seek(10) end },
+    { "n", "shift-Left",  function() seek(-10) end },
+    { "n", "ctrl-r",      cycle_play_mode },
     { "r", "Enter", enter_or_play_selected },
     { "r", "Esc",   show_main },
     { "n", "Space", play_pause },
-    { "ni", "Up,Down,PageUp,PageDown", function(x)
+    { "n", "shift-Up",   volume_up },
+    { "n", "shift-Down", volume_down },
+    { "n", "ctrl-u",     mute },
+    { "n", "Up,Down,PageUp,PageDown", function(x)
       show_current()
       add_char(x)
     end },
@@ -84,7 +97,22 @@ if true then
     end },
     { "a", "ctrl-c",      quit },
     { "n", "ctrl-f,-",    show_favorites },
+    { "n", "ctrl-l",      show_playlists },
+    { "f", "Delete",      remove_from_playlist },
     { "n", "/",           show_directory },
+    { "n", "ctrl-q",      show_queue },
+    { "n", "ctrl-o",      show_history },
+    { "r", "+", function()
+      enqueue(get_selected_song())
+    end },
+    { "r", "*", function()
+      enqueue_next(get_selected_song())
+    end },
+    { "q", "Delete",     dequeue_selected },
+    { "q", "shift-Up",   function() move_queued(-1) end },
+    { "q", "shift-Down", function() move_queued(1) end },
+    { "q", "ctrl-x",     clear_queue },
+    { "r", "ctrl-s",     cycle_sort_order },
     { "d", "/,BackSpace", goto_parent },
     { "a", "]",           next_song },
     { "a", "[",           prev_song },
@@ -97,6 +125,9 @@ else
     { "n", "s",     focus_search },
     { "n", "Left",  prev_subtune },
     { "n", "Right", next_subtune },
+    { "n", "shift-Right", function() seek(10) end },
+    { "n", "shift-Left",  function() seek(-10) end },
+    { "n", "ctrl-r",      cycle_play_mode },
     { "n", "Up,Down,PageUp,PageDown", function(x)
       show_current()
       add_char(x)
@@ -111,8 +142,26 @@ else
     end },
     { "a", "ctrl-c",      quit },
     { "n", "f",           show_favorites },
+    { "n", "l",           show_playlists },
+    { "f", "Delete",      remove_from_playlist },
     { "n", "d,/",         show_directory },
+    { "n", "q",           show_queue },
+    { "n", "h",           show_history },
+    { "r", "+", function()
+      enqueue(get_selected_song())
+    end },
+    { "r", "*", function()
+      enqueue_next(get_selected_song())
+    end },
+    { "q", "Delete",     dequeue_selected },
+    { "q", "shift-Up",   function() move_queued(-1) end },
+    { "q", "shift-Down", function() move_queued(1) end },
+    { "q", "ctrl-x",     clear_queue },
+    { "r", "ctrl-s",     cycle_sort_order },
     { "d", "/,BackSpace", goto_parent },
+    { "n", "+",           volume_up },
+    { "n", "-",           volume_down },
+    { "n", "m",           mute },
     { "n", "n",           next_song },
     { "n", "p",           prev_song },
     { "a", "ctrl-n",      next_song },
@@ -129,6 +178,12 @@ return {
   keys = keys,
   info = "[Any letter] = search, [-] = favorites, [=] = add favorite, [/] = Files",
   settings = {
+    subtunes = { play_all = true },
+    play_time = { sid = 240, nsf = 240, ay = 240, sap = 240 },
+    silence = { seconds = 10, level = -60 },
+    loops = 2,
+    fade_out = 5,
+    crossfade = 0,
     fft = {
       min_freq = 40,
       max_freq = 12000,
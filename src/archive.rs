@@ -0,0 +1,245 @@
+use std::collections::hash_map::DefaultHasher;
+use std::ffi::OsString;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Cursor};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+
+mod lha;
+
+/// Separates the archive from the file inside it, as in `pack.zip#dir/song.mod`
+pub const SEPARATOR: char = '#';
+
+const EXTENSIONS: [&str; 3] = ["zip", "lha", "lzh"];
+
+/// True if `path` is an archive we can look inside
+pub fn is_archive(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| EXTENSIONS.contains(&e.to_string_lossy().to_lowercase().as_str()))
+}
+
+/// Split a path to a file inside an archive into the archive and the name
+/// of the file in it
+pub fn split(path: &Path) -> Option<(PathBuf, String)> {
+    let text = path.to_str()?;
+    text.match_indices(SEPARATOR).find_map(|(i, _)| {
+        let archive = Path::new(&text[..i]);
+        is_archive(archive).then(|| (archive.to_owned(), text[i + 1..].to_owned()))
+    })
+}
+
+/// Path to the file `name` inside `archive`
+pub fn join(archive: &Path, name: &str) -> PathBuf {
+    let mut path = OsString::from(archive);
+    path.push(SEPARATOR.to_string());
+    path.push(name);
+    path.into()
+}
+
+/// The directory `path` is listed in. Files inside an archive are listed
+/// in the archive.
+pub fn parent(path: &Path) -> Option<PathBuf> {
+    match split(path) {
+        Some((archive, _)) => Some(archive),
+        None => path.parent().map(Path::to_owned),
+    }
+}
+
+/// `name` as a relative path, or None if it would end up outside the
+/// directory it is unpacked in
+fn safe_path(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_)))
+        .then(|| path.to_owned())
+}
+
+fn is_zip(archive: &Path) -> bool {
+    archive
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("zip"))
+}
+
+/// Names of the files in `archive`
+pub fn list(archive: &Path) -> Result<Vec<String>> {
+    let data = fs::read(archive)?;
+    if is_zip(archive) {
+        let zip = zip::ZipArchive::new(Cursor::new(data))?;
+        Ok(zip
+            .file_names()
+            .filter(|name| !name.ends_with('/'))
+            .map(str::to_owned)
+            .collect())
+    } else {
+        Ok(lha::entries(&data)?
+            .into_iter()
+            .filter(|entry| !entry.is_dir())
+            .map(|entry| entry.name)
+            .collect())
+    }
+}
+
+/// Unpack the files in `archive` that `wanted` accepts into `dest`.
+/// Returns the names of the unpacked files.
+pub fn unpack(archive: &Path, dest: &Path, wanted: impl Fn(&str) -> bool) -> Result<Vec<String>> {
+    let data = fs::read(archive)?;
+    let mut names = Vec::new();
+    let mut write = |name: String, contents: &mut dyn io::Read| -> Result<()> {
+        let Some(path) = safe_path(&name) else {
+            return Ok(());
+        };
+        let path = dest.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        io::copy(contents, &mut fs::File::create(&path)?)?;
+        names.push(name);
+        Ok(())
+    };
+    if is_zip(archive) {
+        let mut zip = zip::ZipArchive::new(Cursor::new(data))?;
+        for i in 0..zip.len() {
+            let mut file = zip.by_index(i)?;
+            if file.is_dir() || !wanted(file.name()) {
+                continue;
+            }
+            let name = file.name().to_owned();
+            write(name, &mut file)?;
+        }
+    } else {
+        for entry in lha::entries(&data)? {
+            if entry.is_dir() || !wanted(&entry.name) {
+                continue;
+            }
+            let contents = entry.data()?;
+            write(entry.name, &mut contents.as_slice())?;
+        }
+    }
+    Ok(names)
+}
+
+/// Amiga formats keeping the song and the samples in files named
+/// `prefix.name`, like `mdat.x` and `smpl.x`
+const COMPANION_PREFIXES: &[(&str, &str)] = &[
+    ("mdat", "smpl"),
+    ("jpn", "smp"),
+    ("mfp", "smp"),
+    ("dum", "ins"),
+    ("sng", "ins"),
+];
+
+/// Split `file` named like `mdat.x` into its prefix and name
+fn split_prefix(file: &str) -> Option<(&str, &str)> {
+    file.split_once('.').filter(|(prefix, _)| {
+        COMPANION_PREFIXES
+            .iter()
+            .any(|(a, b)| prefix == a || prefix == b)
+    })
+}
+
+/// Files needed to play `song`, like `smpl.x` for `mdat.x` or `x.ins` for `x.mdat`
+pub fn is_companion(song: &str, other: &str) -> bool {
+    let split = |name: &str| {
+        let (dir, file) = name.rsplit_once('/').unwrap_or(("", name));
+        (dir.to_owned(), file.to_lowercase())
+    };
+    let (dir, file) = split(song);
+    let (other_dir, other_file) = split(other);
+    if dir != other_dir || file == other_file {
+        return false;
+    }
+    match (split_prefix(&file), split_prefix(&other_file)) {
+        (Some((prefix, name)), Some((other_prefix, other_name))) => {
+            name == other_name
+                && COMPANION_PREFIXES
+                    .iter()
+                    .any(|&pair| pair == (prefix, other_prefix) || pair == (other_prefix, prefix))
+        }
+        (None, None) => match (file.rsplit_once('.'), other_file.rsplit_once('.')) {
+            (Some((stem, _)), Some((other_stem, _))) => stem == other_stem,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Unpack the song at `path` inside an archive, along with any files it
+/// needs, to a temporary directory. Returns the path of the unpacked song.
+pub fn extract(path: &Path) -> Result<PathBuf> {
+    extract_in(&cache_dir(), path)
+}
+
+/// Where songs are unpacked to, one directory per process
+fn cache_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("oldplay-archives-{}", std::process::id()))
+}
+
+/// Remove the songs unpacked by `extract`
+pub fn clear_cache() {
+    let _ = fs::remove_dir_all(cache_dir());
+}
+
+/// Unpack the song at `path` to a directory for its archive under `cache`
+fn extract_in(cache: &Path, path: &Path) -> Result<PathBuf> {
+    let (archive, name) = split(path).context("Not a path inside an archive")?;
+    let mut hasher = DefaultHasher::new();
+    archive.hash(&mut hasher);
+    fs::metadata(&archive)?.modified()?.hash(&mut hasher);
+    let dest = cache.join(format!("{:016x}", hasher.finish()));
+    let file = dest.join(safe_path(&name).context("Illegal path in archive")?);
+    if !file.is_file() {
+        unpack(&archive, &dest, |n| n == name || is_companion(&name, n))?;
+    }
+    if !file.is_file() {
+        bail!("{name} not found in {archive:?}");
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{extract_in, is_companion, join, list, parent, split, unpack};
+
+    #[test]
+    fn archive_paths_work() {
+        let path = join(Path::new("music/pack.zip"), "mods/song.mod");
+        assert_eq!(path, PathBuf::from("music/pack.zip#mods/song.mod"));
+        assert_eq!(
+            split(&path),
+            Some((PathBuf::from("music/pack.zip"), "mods/song.mod".into()))
+        );
+        assert_eq!(parent(&path), Some(PathBuf::from("music/pack.zip")));
+        assert_eq!(split(Path::new("music/#1 hit.mod")), None);
+        assert!(is_companion("mods/mdat.song", "mods/smpl.song"));
+        assert!(is_companion("song.tfx", "song.sam"));
+        assert!(!is_companion("mods/music.mod", "readme.txt"));
+        assert!(!is_companion("mods/a.mod", "mods/b.mod"));
+        assert!(!is_companion("mods/mdat.a", "mods/mdat.b"));
+        assert!(is_companion("mods/Song.mdat", "mods/song.ins"));
+    }
+
+    #[test]
+    fn songs_are_extracted() {
+        for archive in ["music.zip", "music.lha"] {
+            let cache = tempfile::tempdir().unwrap();
+            let path = join(Path::new(archive), "mods/mdat.music");
+            let song = extract_in(cache.path(), &path).unwrap();
+            assert_eq!(
+                std::fs::read(&song).unwrap(),
+                std::fs::read("music.mod").unwrap()
+            );
+            assert!(song.with_file_name("smpl.music").is_file());
+            let dest = song.parent().unwrap().parent().unwrap();
+            assert!(!dest.join("readme.txt").exists());
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let names = unpack(Path::new("music.zip"), dir.path(), |_| true).unwrap();
+        assert_eq!(names, ["mods/mdat.music", "mods/smpl.music", "readme.txt"]);
+        assert_eq!(list(Path::new("music.lha")).unwrap(), names);
+    }
+}
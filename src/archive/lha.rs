@@ -0,0 +1,394 @@
+use anyhow::{Context, Result, bail};
+
+/// A file in an LHA archive
+pub struct LhaEntry<'a> {
+    /// Path inside the archive, with `/` separators
+    pub name: String,
+    method: [u8; 5],
+    packed: &'a [u8],
+    size: usize,
+    crc: u16,
+}
+
+impl LhaEntry<'_> {
+    pub fn is_dir(&self) -> bool {
+        &self.method == b"-lhd-"
+    }
+
+    /// Decompress the file
+    pub fn data(&self) -> Result<Vec<u8>> {
+        let data = match &self.method {
+            b"-lh0-" => self.packed.to_vec(),
+            b"-lh4-" => Decoder::new(self.packed, 12, 14, 4).decode(self.size)?,
+            b"-lh5-" => Decoder::new(self.packed, 13, 14, 4).decode(self.size)?,
+            b"-lh6-" => Decoder::new(self.packed, 15, 16, 5).decode(self.size)?,
+            b"-lh7-" => Decoder::new(self.packed, 16, 17, 5).decode(self.size)?,
+            method => bail!("Unsupported LHA method {}", String::from_utf8_lossy(method)),
+        };
+        if data.len() != self.size || crc16(&data) != self.crc {
+            bail!("Bad CRC for {}", self.name);
+        }
+        Ok(data)
+    }
+}
+
+/// All entries in the LHA archive `data`
+pub fn entries(mut data: &[u8]) -> Result<Vec<LhaEntry<'_>>> {
+    let mut entries = Vec::new();
+    while let Some(&first) = data.first()
+        && first != 0
+    {
+        let (entry, rest) = parse_header(data)?;
+        entries.push(entry);
+        data = rest;
+    }
+    Ok(entries)
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16> {
+    let bytes = data
+        .get(offset..offset + 2)
+        .context("Truncated LHA header")?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = data
+        .get(offset..offset + 4)
+        .context("Truncated LHA header")?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Parse one header, returning the entry and the data after it.
+/// Header levels 0, 1 and 2 are supported.
+fn parse_header(data: &[u8]) -> Result<(LhaEntry<'_>, &[u8])> {
+    let level = *data.get(20).context("Truncated LHA header")?;
+    let mut method = [0u8; 5];
+    method.copy_from_slice(data.get(2..7).context("Truncated LHA header")?);
+    let mut packed_size = u32_at(data, 7)? as usize;
+    let size = u32_at(data, 11)? as usize;
+
+    let mut name = Vec::new();
+    let mut dir = Vec::new();
+    let crc;
+    // Offset of the first extended header size, and the data
+    let (mut ext, start) = match level {
+        0 | 1 => {
+            let header_size = usize::from(data[0]) + 2;
+            let name_len = usize::from(*data.get(21).context("Truncated LHA header")?);
+            name.extend_from_slice(
+                data.get(22..22 + name_len)
+                    .context("Truncated LHA header")?,
+            );
+            crc = u16_at(data, 22 + name_len)?;
+            if level == 0 {
+                (None, header_size)
+            } else {
+                (Some(header_size - 2), header_size)
+            }
+        }
+        2 => {
+            crc = u16_at(data, 21)?;
+            let header_size = usize::from(u16_at(data, 0)?);
+            if header_size < 26 {
+                bail!("Bad LHA header size");
+            }
+            (Some(24), header_size)
+        }
+        _ => bail!("Unsupported LHA header level {level}"),
+    };
+
+    // Extended headers are a type byte and data, followed by the size of
+    // the next extended header
+    let mut end = start;
+    while let Some(offset) = ext {
+        let next = usize::from(u16_at(data, offset)?);
+        if next == 0 {
+            break;
+        }
+        if next < 3 {
+            bail!("Bad LHA extended header size {next}");
+        }
+        let header = data
+            .get(offset + 2..offset + next)
+            .context("Truncated LHA extended header")?;
+        match header.first() {
+            Some(0x01) => name = header[1..].to_vec(),
+            Some(0x02) => dir = header[1..].to_vec(),
+            _ => {}
+        }
+        ext = Some(offset + next);
+        if level == 1 {
+            // Level 1 extended headers come before the data, and are
+            // counted in the packed size
+            packed_size = packed_size.saturating_sub(next);
+            end += next;
+        }
+    }
+    let start = if level == 1 { end } else { start };
+
+    let packed = data
+        .get(start..start + packed_size)
+        .context("Truncated LHA data")?;
+    let mut path: Vec<u8> = dir
+        .iter()
+        .map(|b| if *b == 0xff { b'/' } else { *b })
+        .collect();
+    if !path.is_empty() && !path.ends_with(b"/") {
+        path.push(b'/');
+    }
+    path.extend(name.iter().map(|b| if *b == b'\\' { b'/' } else { *b }));
+    let entry = LhaEntry {
+        name: String::from_utf8_lossy(&path).into_owned(),
+        method,
+        packed,
+        size,
+        crc,
+    };
+    Ok((entry, &data[start + packed_size..]))
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for b in data {
+        crc ^= u16::from(*b);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xa001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// Reads bits from the most significant bit first
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> u16 {
+        let byte = self.data.get(self.pos / 8).copied().unwrap_or(0);
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        u16::from(bit)
+    }
+
+    fn bits(&mut self, n: u32) -> u16 {
+        (0..n).fold(0, |v, _| (v << 1) | self.bit())
+    }
+}
+
+/// Canonical Huffman code, where shorter codes come first and codes of the
+/// same length are in symbol order
+#[derive(Default)]
+struct Huffman {
+    counts: [u16; 17],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut huffman = Huffman::default();
+        for len in lengths {
+            huffman.counts[usize::from(*len)] += 1;
+        }
+        huffman.counts[0] = 0;
+        for len in 1..=16 {
+            for (symbol, l) in lengths.iter().enumerate() {
+                if usize::from(*l) == len {
+                    huffman.symbols.push(symbol as u16);
+                }
+            }
+        }
+        huffman
+    }
+
+    /// A code with only one symbol, which takes no bits
+    fn single(symbol: u16) -> Huffman {
+        Huffman {
+            counts: [0; 17],
+            symbols: vec![symbol],
+        }
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> Result<u16> {
+        if self.counts.iter().all(|c| *c == 0) {
+            return self.symbols.first().copied().context("Empty Huffman code");
+        }
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..=16 {
+            code |= i32::from(bits.bit());
+            let count = i32::from(self.counts[len]);
+            if code - first < count {
+                return self
+                    .symbols
+                    .get((index + code - first) as usize)
+                    .copied()
+                    .context("Bad Huffman code");
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        bail!("Bad Huffman code")
+    }
+}
+
+/// Number of literal and length symbols
+const NC: usize = 510;
+/// Number of symbols in the code for code lengths
+const NT: usize = 19;
+/// Most memory reserved for decoded data before decoding it
+const MAX_RESERVE: usize = 1 << 20;
+
+/// Decoder for the `-lh4-` to `-lh7-` methods: LZ77 with Huffman coded
+/// blocks
+struct Decoder<'a> {
+    bits: BitReader<'a>,
+    dict_bits: u32,
+    positions: usize,
+    position_bits: u32,
+    block_left: u16,
+    chars: Huffman,
+    offsets: Huffman,
+}
+
+impl Decoder<'_> {
+    fn new(data: &[u8], dict_bits: u32, positions: usize, position_bits: u32) -> Decoder<'_> {
+        Decoder {
+            bits: BitReader { data, pos: 0 },
+            dict_bits,
+            positions,
+            position_bits,
+            block_left: 0,
+            chars: Huffman::default(),
+            offsets: Huffman::default(),
+        }
+    }
+
+    /// Read code lengths for the code length code or the offset code
+    fn read_lengths(
+        &mut self,
+        count: usize,
+        nbits: u32,
+        special: Option<usize>,
+    ) -> Result<Huffman> {
+        let n = usize::from(self.bits.bits(nbits));
+        if n == 0 {
+            return Ok(Huffman::single(self.bits.bits(nbits)));
+        }
+        if n > count {
+            bail!("Bad LHA code length count");
+        }
+        let mut lengths = vec![0u8; count];
+        let mut i = 0;
+        while i < n {
+            let mut len = self.bits.bits(3) as u8;
+            if len == 7 {
+                while self.bits.bit() == 1 {
+                    len += 1;
+                    if len > 16 {
+                        bail!("Bad LHA code length");
+                    }
+                }
+            }
+            lengths[i] = len;
+            i += 1;
+            if Some(i) == special {
+                i += usize::from(self.bits.bits(2));
+            }
+        }
+        Ok(Huffman::new(&lengths))
+    }
+
+    fn read_char_lengths(&mut self, lengths_code: &Huffman) -> Result<Huffman> {
+        let n = usize::from(self.bits.bits(9));
+        if n == 0 {
+            return Ok(Huffman::single(self.bits.bits(9)));
+        }
+        if n > NC {
+            bail!("Bad LHA code length count");
+        }
+        let mut lengths = vec![0u8; NC];
+        let mut i = 0;
+        while i < n {
+            match lengths_code.decode(&mut self.bits)? {
+                // Runs of unused symbols
+                0 => i += 1,
+                1 => i += usize::from(self.bits.bits(4)) + 3,
+                2 => i += usize::from(self.bits.bits(9)) + 20,
+                len => {
+                    lengths[i] = (len - 2) as u8;
+                    i += 1;
+                }
+            }
+        }
+        Ok(Huffman::new(&lengths))
+    }
+
+    fn read_block_header(&mut self) -> Result<()> {
+        self.block_left = self.bits.bits(16);
+        let lengths_code = self.read_lengths(NT, 5, Some(3))?;
+        self.chars = self.read_char_lengths(&lengths_code)?;
+        self.offsets = self.read_lengths(self.positions, self.position_bits, None)?;
+        Ok(())
+    }
+
+    fn decode(mut self, size: usize) -> Result<Vec<u8>> {
+        // The size comes from the header, so do not trust it with the
+        // memory up front
+        let mut out = Vec::with_capacity(size.min(MAX_RESERVE));
+        let window = 1usize << self.dict_bits;
+        while out.len() < size {
+            if self.block_left == 0 {
+                self.read_block_header()?;
+            }
+            self.block_left = self.block_left.wrapping_sub(1);
+            let c = self.chars.decode(&mut self.bits)?;
+            if c < 256 {
+                out.push(c as u8);
+                continue;
+            }
+            let len = usize::from(c) - 253;
+            let p = u32::from(self.offsets.decode(&mut self.bits)?);
+            let offset = if p == 0 {
+                0
+            } else {
+                (1usize << (p - 1)) + usize::from(self.bits.bits(p - 1))
+            };
+            if offset >= out.len() || offset >= window {
+                bail!("Bad LHA match offset");
+            }
+            let from = out.len() - offset - 1;
+            for i in 0..len.min(size - out.len()) {
+                out.push(out[from + i]);
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{crc16, entries};
+
+    #[test]
+    fn lha_files_are_unpacked() {
+        let data = std::fs::read("music.lha").unwrap();
+        let entries = entries(&data).unwrap();
+        let names: Vec<_> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["mods/mdat.music", "mods/smpl.music", "readme.txt"]);
+        assert_eq!(
+            entries[0].data().unwrap(),
+            std::fs::read("music.mod").unwrap()
+        );
+        assert_eq!(entries[1].data().unwrap(), b"sample data for music.mod");
+        assert!(entries[2].data().unwrap().starts_with(b"Songs from"));
+        assert_eq!(crc16(b"123456789"), 0xbb3d);
+    }
+}
@@ -0,0 +1,330 @@
+use anyhow::{Result, bail};
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Something that can consume interleaved stereo float samples, like
+/// an audio file on disk.
+pub trait SampleWriter: Send {
+    fn write(&mut self, samples: &[f32]) -> Result<()>;
+    /// Flush what has been written and patch up any headers, so the file
+    /// can be played even if we are stopped before `finish()`
+    fn sync(&mut self) -> Result<()>;
+    /// Flush remaining data and patch up any headers
+    fn finish(&mut self) -> Result<()>;
+}
+
+const CHANNELS: u16 = 2;
+const BITS_PER_SAMPLE: u16 = 16;
+
+fn to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * 32767.0) as i16
+}
+
+/// Create a writer for `path`, choosing the format from the file extension.
+/// A path of `-` writes raw PCM to stdout.
+pub fn create_writer(path: &Path, freq: u32) -> Result<Box<dyn SampleWriter>> {
+    if path == Path::new("-") {
+        return Ok(Box::new(RawWriter::new(io::stdout())));
+    }
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    Ok(match ext.as_str() {
+        "wav" => Box::new(WavWriter::new(File::create(path)?, freq)?),
+        "flac" => Box::new(FlacWriter::new(File::create(path)?, freq)?),
+        _ => bail!("Unsupported output format '{ext}' (use .wav or .flac)"),
+    })
+}
+
+// RAW
+
+/// Writes headerless 16 bit little endian stereo PCM, for piping to other
+/// programs.
+pub struct RawWriter<W: Write + Send> {
+    out: BufWriter<W>,
+}
+
+impl<W: Write + Send> RawWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out: BufWriter::new(out),
+        }
+    }
+}
+
+impl<W: Write + Send> SampleWriter for RawWriter<W> {
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
+        for s in samples {
+            self.out.write_all(&to_i16(*s).to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.sync()
+    }
+}
+
+// WAV
+
+/// Writes 16 bit stereo PCM in a RIFF/WAVE container. Sizes in the header
+/// are patched in `finish()`.
+pub struct WavWriter<W: Write + Seek + Send> {
+    out: BufWriter<W>,
+    data_bytes: u32,
+}
+
+impl<W: Write + Seek + Send> WavWriter<W> {
+    pub fn new(out: W, freq: u32) -> Result<Self> {
+        let mut out = BufWriter::new(out);
+        let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?; // PCM
+        out.write_all(&CHANNELS.to_le_bytes())?;
+        out.write_all(&freq.to_le_bytes())?;
+        out.write_all(&(freq * u32::from(block_align)).to_le_bytes())?;
+        out.write_all(&block_align.to_le_bytes())?;
+        out.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        Ok(Self { out, data_bytes: 0 })
+    }
+}
+
+impl<W: Write + Seek + Send> SampleWriter for WavWriter<W> {
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
+        for s in samples {
+            self.out.write_all(&to_i16(*s).to_le_bytes())?;
+        }
+        self.data_bytes = self.data_bytes.saturating_add((samples.len() * 2) as u32);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(36 + self.data_bytes).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&self.data_bytes.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.sync()
+    }
+}
+
+// FLAC
+
+const FLAC_BLOCK_SIZE: usize = 4096;
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |mut crc, b| {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |mut crc, b| {
+        crc ^= u16::from(*b) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// Encode a frame number the way FLAC does (extended UTF-8 style)
+fn push_coded_number(target: &mut Vec<u8>, n: u64) {
+    if n < 0x80 {
+        target.push(n as u8);
+        return;
+    }
+    let mut bytes = 2;
+    while n >= 1 << (5 * bytes + 1) {
+        bytes += 1;
+    }
+    let first_mask = !(0xffu8 >> bytes);
+    target.push(first_mask | (n >> (6 * (bytes - 1))) as u8);
+    for i in (0..bytes - 1).rev() {
+        target.push(0x80 | ((n >> (6 * i)) & 0x3f) as u8);
+    }
+}
+
+/// A minimal FLAC encoder that stores all audio in verbatim subframes.
+/// No compression, but the result is a valid FLAC file that any decoder
+/// can read, with correct stream info.
+pub struct FlacWriter<W: Write + Seek + Send> {
+    out: BufWriter<W>,
+    freq: u32,
+    pending: Vec<i16>,
+    frame_number: u64,
+    total_frames: u64,
+}
+
+impl<W: Write + Seek + Send> FlacWriter<W> {
+    pub fn new(out: W, freq: u32) -> Result<Self> {
+        let mut writer = Self {
+            out: BufWriter::new(out),
+            freq,
+            pending: Vec::with_capacity(FLAC_BLOCK_SIZE * 2),
+            frame_number: 0,
+            total_frames: 0,
+        };
+        writer.out.write_all(b"fLaC")?;
+        writer.write_stream_info()?;
+        Ok(writer)
+    }
+
+    fn write_stream_info(&mut self) -> Result<()> {
+        // Last metadata block, type 0 (STREAMINFO), 34 bytes
+        self.out.write_all(&[0x80, 0, 0, 34])?;
+        let block_size = FLAC_BLOCK_SIZE as u16;
+        self.out.write_all(&block_size.to_be_bytes())?;
+        self.out.write_all(&block_size.to_be_bytes())?;
+        // Unknown min/max frame size
+        self.out.write_all(&[0; 6])?;
+        // 20 bits rate, 3 bits channels-1, 5 bits bps-1, 36 bits total samples
+        let packed: u64 = (u64::from(self.freq) << 44)
+            | (u64::from(CHANNELS - 1) << 41)
+            | (u64::from(BITS_PER_SAMPLE - 1) << 36)
+            | (self.total_frames & 0xf_ffff_ffff);
+        self.out.write_all(&packed.to_be_bytes())?;
+        // No MD5 signature
+        self.out.write_all(&[0; 16])?;
+        Ok(())
+    }
+
+    fn write_frame(&mut self, samples: &[i16]) -> Result<()> {
+        let frames = samples.len() / 2;
+        if frames == 0 {
+            return Ok(());
+        }
+        let mut data = Vec::with_capacity(16 + samples.len() * 2 + 2);
+        // Sync code, fixed block size
+        data.extend_from_slice(&[0xff, 0xf8]);
+        // Block size from end of header (16 bit), sample rate from stream info
+        data.push(0x70);
+        // Independent stereo, 16 bits per sample
+        data.push(0x18);
+        push_coded_number(&mut data, self.frame_number);
+        data.extend_from_slice(&((frames - 1) as u16).to_be_bytes());
+        data.push(crc8(&data));
+        for channel in 0..2 {
+            // Verbatim subframe, no wasted bits
+            data.push(0x02);
+            for frame in samples.chunks_exact(2) {
+                data.extend_from_slice(&frame[channel].to_be_bytes());
+            }
+        }
+        let crc = crc16(&data);
+        data.extend_from_slice(&crc.to_be_bytes());
+        self.out.write_all(&data)?;
+        self.frame_number += 1;
+        self.total_frames += frames as u64;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek + Send> SampleWriter for FlacWriter<W> {
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
+        for s in samples {
+            self.pending.push(to_i16(*s));
+            if self.pending.len() == FLAC_BLOCK_SIZE * 2 {
+                let block = std::mem::take(&mut self.pending);
+                self.write_frame(&block)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Only whole blocks are written, the rest waits for `finish()`
+    fn sync(&mut self) -> Result<()> {
+        self.out.seek(SeekFrom::Start(4))?;
+        self.write_stream_info()?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let block = std::mem::take(&mut self.pending);
+        self.write_frame(&block)?;
+        self.sync()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{FlacWriter, SampleWriter, WavWriter, push_coded_number};
+
+    #[test]
+    fn wav_header_is_patched() {
+        let mut cursor = Cursor::new(Vec::new());
+        {
+            let mut writer = WavWriter::new(&mut cursor, 44100).unwrap();
+            writer.write(&[0.0, 0.5]).unwrap();
+            writer.sync().unwrap();
+            writer.write(&[-0.5, 1.0]).unwrap();
+            writer.finish().unwrap();
+        }
+        let data = cursor.into_inner();
+        assert_eq!(data.len(), 44 + 8);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(data[4..8].try_into().unwrap()), 44);
+        assert_eq!(u32::from_le_bytes(data[40..44].try_into().unwrap()), 8);
+    }
+
+    #[test]
+    fn flac_stream_info_has_total_samples() {
+        let mut cursor = Cursor::new(Vec::new());
+        {
+            let mut writer = FlacWriter::new(&mut cursor, 44100).unwrap();
+            writer.write(&vec![0.25; 5000 * 2]).unwrap();
+            writer.finish().unwrap();
+        }
+        let data = cursor.into_inner();
+        assert_eq!(&data[0..4], b"fLaC");
+        let packed = u64::from_be_bytes(data[18..26].try_into().unwrap());
+        assert_eq!(packed >> 44, 44100);
+        assert_eq!(packed & 0xf_ffff_ffff, 5000);
+    }
+
+    #[test]
+    fn coded_numbers_match_utf8() {
+        for n in [0u32, 0x7f, 0x80, 0x7ff, 0x800, 0xffff, 0x10000] {
+            let mut target = Vec::new();
+            push_coded_number(&mut target, u64::from(n));
+            let expected = char::from_u32(n).map(|c| c.to_string().into_bytes());
+            if let Some(expected) = expected {
+                assert_eq!(target, expected);
+            }
+        }
+    }
+}
@@ -6,14 +6,18 @@
 )]
 
 use std::{
+    collections::HashMap,
     fs::{File, OpenOptions},
     io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{LazyLock, Mutex},
 };
 
+pub mod archive;
+pub mod audio_writer;
 pub mod media_keys;
 pub mod player;
+pub mod render;
 pub mod resampler;
 pub mod rustplay;
 pub mod templ;
@@ -23,7 +27,7 @@ pub mod value;
 
 pub use rustplay::RustPlay;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use serde::Deserialize;
 
 /// Log text to the '.rustplay.log' file
@@ -82,11 +86,73 @@ impl Default for FFtSettings {
     }
 }
 
+/// Seconds to play subtunes with no known length when playing all subtunes,
+/// if no play time is set for their format
+const DEFAULT_SUBTUNE_TIMEOUT: f64 = 150.0;
+
+#[derive(Default, Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct SubtuneSettings {
+    /// Play all subtunes of a file before going on to the next file
+    play_all: bool,
+}
+
+/// The value in `by_extension` for the extension of `path`, or the
+/// `default` value
+fn for_extension(by_extension: &HashMap<String, f64>, path: &Path) -> Option<f64> {
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    by_extension
+        .get(&ext)
+        .or(by_extension.get("default"))
+        .copied()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct SilenceSettings {
+    /// Seconds of silence before a song is considered done, 0 = never
+    seconds: f64,
+    /// Level in dB below which audio counts as silence
+    level: f64,
+}
+
+impl Default for SilenceSettings {
+    fn default() -> Self {
+        Self {
+            seconds: 10.0,
+            level: -60.0,
+        }
+    }
+}
+
 #[derive(Default, Debug, Deserialize, Clone)]
 #[serde(default)]
 pub struct Settings {
     fft: FFtSettings,
+    subtunes: SubtuneSettings,
+    silence: SilenceSettings,
+    /// Number of times to play songs that loop, 0 = don't look for loops
+    loops: u32,
+    /// Seconds to fade out songs before they end
+    fade_out: f64,
+    /// Seconds to mix the end of a song with the start of the next, 0 = off
+    crossfade: f64,
+    /// Seconds to play songs with no known length, by file extension, with
+    /// `default` for extensions not listed. Other songs play until they end.
+    play_time: HashMap<String, f64>,
     no_color: bool,
+    /// Allow typos in all searches, not just those starting with `~`
+    fuzzy_search: bool,
+}
+
+impl Settings {
+    /// Seconds to play `path` if it has no known length
+    fn play_time(&self, path: &Path) -> Option<f64> {
+        for_extension(&self.play_time, path)
+    }
 }
 
 #[derive(Default, Parser, Debug, Clone)]
@@ -105,6 +171,40 @@ pub struct Args {
 
     #[arg(long, default_value_t = false)]
     ignore_cache: bool,
+
+    /// Write audio to a WAV/FLAC file instead of the sound card ('-' for raw PCM on stdout)
+    #[arg(long)]
+    audio_file: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Render a song to a WAV or FLAC file, as fast as possible
+    Render(RenderArgs),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RenderArgs {
+    /// Song file to render
+    song: PathBuf,
+
+    /// Subtune to render (first subtune is 1)
+    #[arg(long, short = 's')]
+    subtune: Option<i32>,
+
+    /// Seconds to render (default is the song length, if known)
+    #[arg(long)]
+    seconds: Option<f64>,
+
+    /// Output file, format given by extension (.wav or .flac)
+    #[arg(long, short = 'o')]
+    output: PathBuf,
+
+    #[arg(long, default_value_t = 44100)]
+    freq: u32,
 }
 
 pub const CONFIG_LUA: &str = include_str!("../config.lua");
@@ -3,8 +3,8 @@ use clap::Parser;
 use oldplay::CONFIG_LUA;
 use std::{error::Error, panic, process, time::Duration};
 
-use oldplay::Args;
 use oldplay::RustPlay;
+use oldplay::{Args, Command};
 
 use anyhow::Result;
 
@@ -20,6 +20,11 @@ fn main() -> Result<(), Box<dyn Error>> {
         std::process::exit(0);
     }
 
+    if let Some(Command::Render(render_args)) = &args.command {
+        oldplay::render::render(render_args)?;
+        std::process::exit(0);
+    }
+
     panic::set_hook(Box::new(move |panic_info| {
         RustPlay::restore_term().expect("Could not restore terminal");
         println!("panic occurred: {panic_info}");
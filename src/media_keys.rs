@@ -1,7 +1,8 @@
-use std::sync::mpsc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, mpsc};
 
 /// Media key events that can be listened to
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum MediaKeyEvent {
     Next,
     Previous,
@@ -9,14 +10,22 @@ pub enum MediaKeyEvent {
     Play,
     Pause,
     Stop,
+    /// Relative seek in microseconds
+    Seek(i64),
+    /// Absolute position in microseconds
+    SetPosition(i64),
+    /// Volume from 0 to 1
+    SetVolume(f64),
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum MediaKeyInfo {
     Author(String),
     Title(String),
     Playing,
     Paused,
+    /// Volume from 0 to 1
+    Volume(f64),
     Shutdown,
 }
 
@@ -25,8 +34,8 @@ pub enum MediaKeyInfo {
 mod linux_impl {
     use super::*;
     use crate::log;
-    use std::sync::Arc;
     use std::sync::Mutex;
+    use std::sync::atomic::Ordering;
     use std::thread;
     use std::time::Duration;
     use zbus::Connection;
@@ -37,6 +46,7 @@ mod linux_impl {
         is_playing: bool,
         title: String,
         author: String,
+        volume: f64,
     }
 
     /// Main MPRIS interface implementation
@@ -85,16 +95,19 @@ mod linux_impl {
     pub struct MediaPlayer {
         play_state: Arc<Mutex<PlayState>>,
         event_sender: mpsc::Sender<MediaKeyEvent>,
+        position_ms: Arc<AtomicUsize>,
     }
 
     impl MediaPlayer {
         fn new(
             play_state: Arc<Mutex<PlayState>>,
             event_sender: mpsc::Sender<MediaKeyEvent>,
+            position_ms: Arc<AtomicUsize>,
         ) -> Self {
             MediaPlayer {
                 play_state,
                 event_sender,
+                position_ms,
             }
         }
     }
@@ -146,12 +159,18 @@ mod linux_impl {
 
         #[zbus(property)]
         fn volume(&self) -> f64 {
-            1.0
+            self.play_state.lock().map_or(1.0, |ps| ps.volume)
+        }
+
+        #[zbus(property)]
+        fn set_volume(&self, volume: f64) {
+            log!("[MPRIS] Volume {volume}");
+            let _ = self.event_sender.send(MediaKeyEvent::SetVolume(volume));
         }
 
         #[zbus(property)]
         fn position(&self) -> i64 {
-            0
+            self.position_ms.load(Ordering::Relaxed) as i64 * 1000
         }
 
         #[zbus(property)]
@@ -196,7 +215,7 @@ mod linux_impl {
 
         #[zbus(property)]
         fn can_seek(&self) -> bool {
-            false
+            true
         }
 
         #[zbus(property)]
@@ -238,12 +257,29 @@ mod linux_impl {
             let _ = self.event_sender.send(MediaKeyEvent::Stop);
             Ok(())
         }
+
+        fn seek(&self, offset: i64) -> zbus::fdo::Result<()> {
+            log!("[MPRIS] Seek {offset}");
+            let _ = self.event_sender.send(MediaKeyEvent::Seek(offset));
+            Ok(())
+        }
+
+        fn set_position(
+            &self,
+            _track_id: zbus::zvariant::ObjectPath<'_>,
+            position: i64,
+        ) -> zbus::fdo::Result<()> {
+            log!("[MPRIS] SetPosition {position}");
+            let _ = self.event_sender.send(MediaKeyEvent::SetPosition(position));
+            Ok(())
+        }
     }
 
     /// Start the MPRIS listener in a background thread
     /// Returns (shutdown_sender, event_receiver, service_name)
     pub fn start_with_name(
         service_name: &str,
+        position_ms: Arc<AtomicUsize>,
     ) -> (
         mpsc::Sender<MediaKeyInfo>,
         mpsc::Receiver<MediaKeyEvent>,
@@ -266,6 +302,7 @@ mod linux_impl {
                     state_clone,
                     &service_name_for_thread,
                     event_sender,
+                    position_ms,
                 )
                 .await
                 {
@@ -287,6 +324,7 @@ mod linux_impl {
         play_state: Arc<Mutex<PlayState>>,
         service_name: &str,
         event_sender: mpsc::Sender<MediaKeyEvent>,
+        position_ms: Arc<AtomicUsize>,
     ) -> Result<(), zbus::Error> {
         let connection = Connection::session().await?;
 
@@ -295,7 +333,7 @@ mod linux_impl {
 
         // Register both the main interface and the player interface at the standard MPRIS path
         let main = MainInterface::new(event_sender.clone());
-        let player = MediaPlayer::new(play_state.clone(), event_sender);
+        let player = MediaPlayer::new(play_state.clone(), event_sender, position_ms);
 
         connection
             .object_server()
@@ -320,6 +358,7 @@ mod linux_impl {
                     MediaKeyInfo::Paused => ps.is_playing = false,
                     MediaKeyInfo::Title(title) => ps.title = title,
                     MediaKeyInfo::Author(author) => ps.author = author,
+                    MediaKeyInfo::Volume(volume) => ps.volume = volume,
                 }
             } else {
                 tokio::time::sleep(Duration::from_millis(100)).await;
@@ -334,14 +373,20 @@ mod linux_impl {
 }
 
 // Public API - works on all platforms
+/// `position_ms` is the current play position, reported to media controllers
 #[cfg(target_os = "linux")]
-pub fn start() -> (mpsc::Sender<MediaKeyInfo>, mpsc::Receiver<MediaKeyEvent>) {
-    let (sender, receiver, _) = linux_impl::start_with_name("org.mpris.MediaPlayer2.oldplay");
+pub fn start(
+    position_ms: Arc<AtomicUsize>,
+) -> (mpsc::Sender<MediaKeyInfo>, mpsc::Receiver<MediaKeyEvent>) {
+    let (sender, receiver, _) =
+        linux_impl::start_with_name("org.mpris.MediaPlayer2.oldplay", position_ms);
     (sender, receiver)
 }
 
 #[cfg(not(target_os = "linux"))]
-pub fn start() -> (mpsc::Sender<MediaKeyInfo>, mpsc::Receiver<MediaKeyEvent>) {
+pub fn start(
+    _position_ms: Arc<AtomicUsize>,
+) -> (mpsc::Sender<MediaKeyInfo>, mpsc::Receiver<MediaKeyEvent>) {
     use std::thread;
     use std::time::Duration;
 
@@ -22,10 +22,20 @@ use musix::MusicError;
 
 mod audio_device;
 mod cpal_device;
+mod fade;
 mod fft;
+mod file_device;
+mod loop_detect;
+mod preload;
+mod silence;
 
 use audio_device::{AudioCallback, AudioDevice};
 use cpal_device::setup_audio_device;
+use fade::{Crossfade, Fade, Ramp};
+use file_device::FileDevice;
+use loop_detect::LoopDetector;
+use preload::Preloader;
+use silence::Silence;
 
 use musix::MusixPlayer;
 
@@ -41,6 +51,18 @@ impl AudioBackend for CpalBackend {
     }
 }
 
+/// Writes audio to a file (or raw PCM to stdout if path is `-`) instead of
+/// playing it.
+pub(crate) struct FileBackend {
+    pub path: PathBuf,
+}
+
+impl AudioBackend for FileBackend {
+    fn setup_audio_device(&self) -> Result<Box<dyn AudioDevice>> {
+        Ok(Box::new(FileDevice::new(self.path.clone())))
+    }
+}
+
 pub(crate) struct NoSoundDevice {
     buffer_size: usize,
     playback_freq: u32,
@@ -143,17 +165,94 @@ pub fn init_music() {
 #[derive(Default)]
 pub(crate) struct Player {
     chip_player: Option<Box<dyn MusixPlayer>>,
+    /// The playing subtune, 0 = first
     song: i32,
     songs: i32,
     millis: Arc<AtomicUsize>,
     play_state: PlayState,
     ff_msec: usize,
     new_song: Option<PathBuf>,
+    song_path: Option<PathBuf>,
+    native_seek: bool,
+    /// `done` was sent for the playing song, and should not be sent again
+    done_sent: bool,
+    silence: Silence,
+    loops: LoopDetector,
+    /// Set while the song is fading out, before it ends
+    fade: Option<Fade>,
+    /// How long to fade out songs that loop
+    fade_out_msec: usize,
+    /// The previous song, fading out while this one starts
+    outgoing: Option<Crossfade>,
+    /// Gets the song expected to be played next ready in the background
+    preloader: Option<Preloader>,
+    volume: Ramp,
+}
+
+/// Extensions of formats where the plugin can seek in time by itself
+const NATIVE_SEEK_EXTENSIONS: [&str; 1] = ["ym"];
+
+/// Ends the format names of songs played by UADE. It runs all songs on one
+/// global thread, and aborts if two songs are loaded at once.
+const SINGLE_SONG_FORMAT_SUFFIX: &str = "(Amiga)";
+
+/// Songs in `format` can still be loaded while the next song is loaded, as
+/// crossfading needs
+pub(crate) fn can_crossfade(format: &str) -> bool {
+    !format.ends_with(SINGLE_SONG_FORMAT_SUFFIX)
 }
 
 impl Player {
     pub fn reset(&mut self) {
         self.millis.store(0, Ordering::SeqCst);
+        self.done_sent = false;
+        self.silence.reset();
+        self.loops.reset();
+        self.fade = None;
+    }
+
+    /// Fade the song out over `msec` milliseconds, and then end it
+    #[allow(clippy::unnecessary_wraps)]
+    pub fn fade_out(&mut self, msec: usize) -> PlayResult {
+        if self.fade.is_none() {
+            self.fade = Some(Fade::new(msec));
+        }
+        Ok(true)
+    }
+
+    /// Stop looking for loops in the current song, as its length is known
+    #[allow(clippy::unnecessary_wraps)]
+    pub fn disable_loop_detection(&mut self) -> PlayResult {
+        self.loops.disable();
+        Ok(true)
+    }
+
+    /// Set the volume, from 0 (silent) to 1 (as loud as the song is)
+    #[allow(clippy::unnecessary_wraps)]
+    pub fn set_volume(&mut self, volume: f64) -> PlayResult {
+        let volume = volume.clamp(0.0, 1.0) as f32;
+        if self.chip_player.is_some() {
+            self.volume.set(volume);
+        } else {
+            // Nothing is playing, so there is nothing to ramp
+            self.volume = Ramp::new(volume);
+        }
+        Ok(true)
+    }
+
+    /// Load the song `name`, and keep playing the current song while it
+    /// fades out over `msec` milliseconds
+    pub fn crossfade(&mut self, name: &Path, msec: usize) -> PlayResult {
+        let mut old = self.chip_player.take();
+        if let Some(player) = &mut old
+            && !can_crossfade(&player.get_meta_string("format").unwrap_or_default())
+        {
+            // Unloaded before the new song is loaded
+            old = None;
+        }
+        self.load(name)?;
+        self.outgoing = old.map(|player| Crossfade::new(player, msec));
+        Ok(true)
     }
 
     #[allow(clippy::unnecessary_wraps)]
@@ -182,22 +281,98 @@ impl Player {
     #[allow(clippy::unnecessary_wraps)]
     pub fn set_song(&mut self, song: i32) -> PlayResult {
         if let Some(cp) = &self.chip_player {
-            self.song = song;
-            cp.seek(self.song - 1, 0);
+            self.song = song - 1;
+            cp.seek(self.song, 0);
             self.reset();
         }
         Ok(true)
     }
 
+    /// Start loading the song `name` in `format` in the background, so it
+    /// starts without a gap when it is loaded with `load()`. Songs in
+    /// formats that can't be loaded next to the playing song, or in an
+    /// unknown format, are only unpacked.
+    #[allow(clippy::unnecessary_wraps)]
+    pub fn preload(&mut self, name: &Path, format: &str) -> PlayResult {
+        let load_player = !format.is_empty() && can_crossfade(format);
+        self.preloader
+            .get_or_insert_with(Preloader::start)
+            .preload(name, load_player);
+        Ok(true)
+    }
+
     pub fn load(&mut self, name: &Path) -> PlayResult {
         self.chip_player = None;
-        self.chip_player = Some(musix::load_song(name)?);
+        self.outgoing = None;
+        let preloaded = self.preloader.as_mut().and_then(|p| p.take(name));
+        let (name, player) = match preloaded {
+            Some(song) => {
+                let song = song?;
+                (song.file, song.player)
+            }
+            None => (preload::song_file(name)?, None),
+        };
+        let player = match player {
+            Some(player) => player,
+            None => musix::load_song(&name)?,
+        };
+        self.chip_player = Some(player);
         self.reset();
+        self.ff_msec = 0;
         self.new_song = Some(name.to_owned());
+        self.song_path = Some(name.to_owned());
+        self.native_seek = name
+            .extension()
+            .map(|e| NATIVE_SEEK_EXTENSIONS.contains(&e.to_string_lossy().to_lowercase().as_str()))
+            .unwrap_or(false);
         self.play_state = PlayState::Playing;
         Ok(true)
     }
 
+    /// Current position in msec, including any pending fast forward
+    fn position(&self) -> usize {
+        self.millis.load(Ordering::SeqCst) + self.ff_msec
+    }
+
+    /// Seek to `msec` from the start of the current subtune.
+    ///
+    /// Uses the plugin's own seek if it supports it, otherwise fast forwards
+    /// by decoding, reloading the song first when seeking backwards.
+    pub fn seek(&mut self, msec: usize) -> PlayResult {
+        let cp = self.chip_player.as_ref().ok_or(MusicError {
+            msg: "No active song".into(),
+        })?;
+        self.fade = None;
+        if self.native_seek {
+            let secs = msec / 1000;
+            cp.seek(-1, secs as i32);
+            self.ff_msec = 0;
+            self.millis.store(secs * 1000, Ordering::SeqCst);
+            return Ok(true);
+        }
+        let pos = self.millis.load(Ordering::SeqCst);
+        if msec >= pos {
+            self.ff_msec = msec - pos;
+        } else {
+            let path = self.song_path.clone().ok_or(MusicError {
+                msg: "No active song".into(),
+            })?;
+            self.chip_player = None;
+            let cp = musix::load_song(&path)?;
+            cp.seek(self.song, 0);
+            self.chip_player = Some(cp);
+            self.reset();
+            self.ff_msec = msec;
+        }
+        Ok(true)
+    }
+
+    /// Seek `delta_msec` forward (or backward if negative) from the current position
+    pub fn seek_relative(&mut self, delta_msec: i64) -> PlayResult {
+        let target = (self.position() as i64 + delta_msec).max(0);
+        self.seek(target as usize)
+    }
+
     #[allow(clippy::unnecessary_wraps)]
     pub fn ff(&mut self, msec: usize) -> PlayResult {
         self.ff_msec += msec;
@@ -234,6 +409,7 @@ impl Player {
                 && ext == "mp3"
             {
                 if let Ok(duration) = mp3_duration::from_path(&new_song) {
+                    self.loops.disable();
                     let secs = duration.as_secs() as i32;
                     info_producer.push_value("length", secs)?;
                 }
@@ -270,8 +446,17 @@ impl Player {
                     }
                     "length" => {
                         let length = val.parse::<f64>()?;
+                        // Songs with a known length end by themselves
+                        if length > 0.0 {
+                            self.loops.disable();
+                        }
                         length.into()
                     }
+                    "tracker" => {
+                        // Only reported by the OpenMPT plugin, which can seek
+                        self.native_seek = true;
+                        Value::Text(val)
+                    }
                     &_ => Value::Text(val),
                 };
                 info_producer.push_value(&meta, v)?;
@@ -301,6 +486,7 @@ const IDLE_SLEEP_MS: u64 = 100;
 
 fn run_audio_loop<B: AudioBackend>(
     mut fft: Fft,
+    player: Player,
     mut info_producer: mpsc::Sender<Info>,
     cmd_consumer: mpsc::Receiver<Cmd>,
     msec: Arc<AtomicUsize>,
@@ -335,7 +521,7 @@ fn run_audio_loop<B: AudioBackend>(
     let mut target: Vec<i16> = vec![0; buffer_size];
     let mut player = Player {
         millis: msec_outside,
-        ..Player::default()
+        ..player
     };
 
     let mut last_state = player.play_state;
@@ -367,14 +553,21 @@ fn run_audio_loop<B: AudioBackend>(
                 }
                 msec_skip.fetch_add(ms, Ordering::SeqCst);
                 if rc == 0 {
-                    info_producer.push_value("done", 0)?;
+                    player.ff_msec = 0;
+                    if !player.done_sent {
+                        player.done_sent = true;
+                        info_producer.push_value("done", 0)?;
+                    }
                 }
             } else if audio_sink.vacant_len() > target.len() * 2
                 && player.play_state == PlayState::Playing
             {
                 // Normal playback mode
                 let rc = chip_player.get_samples(&mut target);
-                if rc == 0 {
+                if rc == 0 && !player.done_sent {
+                    // Sent once, so the next song is not skipped while
+                    // this one is still being replaced
+                    player.done_sent = true;
                     info_producer.push_value("done", 0)?;
                 }
 
@@ -392,6 +585,29 @@ fn run_audio_loop<B: AudioBackend>(
                     .take(rc)
                     .map(|&s16| f32::from(s16) / 32767.0)
                     .collect_vec();
+                if player.loops.update(&samples, plugin_freq) && player.fade.is_none() {
+                    log!("Song has looped, fading out");
+                    player.fade = Some(Fade::new(player.fade_out_msec));
+                }
+                if let Some(fade) = &mut player.fade
+                    && fade.apply(&mut samples, plugin_freq)
+                    && !player.done_sent
+                {
+                    player.done_sent = true;
+                    info_producer.push_value("done", 0)?;
+                }
+                if let Some(outgoing) = &mut player.outgoing
+                    && outgoing.mix(&mut samples, plugin_freq)
+                {
+                    player.outgoing = None;
+                }
+                // Before the volume, so muted songs are not taken as silent
+                if player.silence.update(&samples, plugin_freq) && !player.done_sent {
+                    log!("Song is silent, moving on");
+                    player.done_sent = true;
+                    info_producer.push_value("done", 0)?;
+                }
+                player.volume.apply(&mut samples, plugin_freq);
                 let new_samples = resampler.process(&samples)?;
                 audio_sink.push_slice(new_samples);
 
@@ -439,11 +655,21 @@ pub(crate) fn run_player<B: AudioBackend + Send + 'static>(
         ..Default::default()
     };
 
+    let silence = Silence::new(settings.silence.level, settings.silence.seconds);
+    let loops = LoopDetector::new(settings.loops as usize);
+    let fade_out_msec = (settings.fade_out.max(0.0) * 1000.0) as usize;
     let info_producer_error = info_producer.clone();
 
     Ok(thread::spawn(move || -> Result<()> {
+        let player = Player {
+            silence,
+            loops,
+            fade_out_msec,
+            ..Player::default()
+        };
         let result = run_audio_loop(
             fft,
+            player,
             info_producer,
             cmd_consumer,
             msec,
@@ -463,7 +689,7 @@ pub(crate) fn run_player<B: AudioBackend + Send + 'static>(
 mod tests {
     use std::path::Path;
     use std::sync::Arc;
-    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::mpsc;
 
     use crate::Settings;
@@ -485,6 +711,54 @@ mod tests {
         assert_eq!(rc, 8192);
     }
 
+    #[test]
+    fn seek_fast_forwards_or_reloads() {
+        init_music();
+        let mut player = super::Player::default();
+        assert!(player.seek(1000).is_err());
+
+        player.load(Path::new("music.s3m")).unwrap();
+        player.native_seek = false;
+        player.seek_relative(5000).unwrap();
+        assert_eq!(player.ff_msec, 5000);
+        player.ff_msec = 0;
+        player.millis.store(8000, Ordering::SeqCst);
+
+        // Backwards seek reloads and fast forwards from the start
+        player.seek_relative(-5000).unwrap();
+        assert_eq!(player.millis.load(Ordering::SeqCst), 0);
+        assert_eq!(player.ff_msec, 3000);
+    }
+
+    #[test]
+    fn reload_keeps_subtune() {
+        init_music();
+        let mut player = super::Player::default();
+        player.load(Path::new("Chimera.sid")).unwrap();
+        // Subtunes are set from 1, but kept from 0 like the plugins do
+        player.set_song(2).unwrap();
+        assert_eq!(player.song, 1);
+        player.millis.store(8000, Ordering::SeqCst);
+        player.seek(1000).unwrap();
+        assert_eq!(player.song, 1);
+        assert_eq!(player.ff_msec, 1000);
+    }
+
+    #[test]
+    fn next_song_is_preloaded() {
+        init_music();
+        let mut preloader = super::Preloader::start();
+        preloader.preload(Path::new("music.mod"), true);
+        let song = preloader.take(Path::new("music.mod")).unwrap().unwrap();
+        assert_eq!(song.file, Path::new("music.mod"));
+        assert!(song.player.is_some());
+
+        // Only unpacked for plugins that can't load two songs
+        preloader.preload(Path::new("music.s3m"), false);
+        let song = preloader.take(Path::new("music.s3m")).unwrap().unwrap();
+        assert!(song.player.is_none());
+    }
+
     #[test]
     fn player_starts() {
         init_music();
@@ -517,6 +791,44 @@ mod tests {
         player_thread.join().unwrap().unwrap();
     }
 
+    #[test]
+    fn player_writes_audio_file() {
+        init_music();
+        let (cmd_producer, cmd_consumer) = mpsc::channel::<Cmd>();
+        let (info_producer, info_consumer) = mpsc::channel::<Info>();
+        let msec = Arc::new(AtomicUsize::new(0));
+        let audio_delay_us = Arc::new(AtomicUsize::new(0));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output.wav");
+        let backend = super::FileBackend { path: path.clone() };
+        let player_thread = crate::player::run_player(
+            &Settings::default(),
+            info_producer,
+            cmd_consumer,
+            msec,
+            audio_delay_us,
+            backend,
+        )
+        .unwrap();
+
+        cmd_producer
+            .send(Box::new(move |p| p.load(Path::new("music.mod"))))
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        cmd_producer.send(Box::new(move |p| p.quit())).unwrap();
+        loop {
+            let (key, _) = info_consumer.recv().unwrap();
+            if key == "quit" {
+                break;
+            }
+        }
+        player_thread.join().unwrap().unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(&data[0..4], b"RIFF");
+        assert!(data.len() > 44);
+    }
+
     #[test]
     fn player_can_report_errors() {
         init_music();
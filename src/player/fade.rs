@@ -0,0 +1,206 @@
+use musix::MusixPlayer;
+
+/// Fades audio out over a number of milliseconds
+#[derive(Debug)]
+pub struct Fade {
+    total_msec: f64,
+    left_msec: f64,
+}
+
+/// Milliseconds per stereo sample at `freq` Hz
+fn step(freq: u32) -> f64 {
+    1000.0 / f64::from(freq.max(1))
+}
+
+impl Fade {
+    pub fn new(msec: usize) -> Self {
+        Self {
+            total_msec: msec as f64,
+            left_msec: msec as f64,
+        }
+    }
+
+    /// Gain for the next stereo sample, going from 1 down to 0
+    fn next_gain(&mut self, step: f64) -> f32 {
+        let gain = if self.total_msec > 0.0 {
+            (self.left_msec / self.total_msec) as f32
+        } else {
+            0.0
+        };
+        self.left_msec = (self.left_msec - step).max(0.0);
+        gain
+    }
+
+    fn is_done(&self) -> bool {
+        self.left_msec <= 0.0
+    }
+
+    /// Fade the interleaved stereo `samples` played at `freq` Hz. Returns
+    /// true once the fade is done, after which all samples are silent.
+    pub fn apply(&mut self, samples: &mut [f32], freq: u32) -> bool {
+        let step = step(freq);
+        for pair in samples.chunks_exact_mut(2) {
+            let gain = self.next_gain(step);
+            pair[0] *= gain;
+            pair[1] *= gain;
+        }
+        self.is_done()
+    }
+}
+
+/// Milliseconds to go between silent and full volume
+const RAMP_MSEC: f64 = 50.0;
+
+/// A gain that moves smoothly to new values, so that changing the volume
+/// does not make clicks
+#[derive(Debug)]
+pub struct Ramp {
+    gain: f32,
+    target: f32,
+}
+
+impl Default for Ramp {
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+
+impl Ramp {
+    pub fn new(gain: f32) -> Self {
+        Self { gain, target: gain }
+    }
+
+    pub fn set(&mut self, target: f32) {
+        self.target = target;
+    }
+
+    /// Apply the gain to the interleaved stereo `samples` played at `freq` Hz
+    pub fn apply(&mut self, samples: &mut [f32], freq: u32) {
+        if self.gain == self.target && self.gain == 1.0 {
+            return;
+        }
+        let step = (step(freq) / RAMP_MSEC) as f32;
+        for pair in samples.chunks_exact_mut(2) {
+            if self.gain < self.target {
+                self.gain = (self.gain + step).min(self.target);
+            } else if self.gain > self.target {
+                self.gain = (self.gain - step).max(self.target);
+            }
+            pair[0] *= self.gain;
+            pair[1] *= self.gain;
+        }
+    }
+}
+
+/// A song that keeps playing while it fades out, and the next song fades in
+pub struct Crossfade {
+    player: Box<dyn MusixPlayer>,
+    fade: Fade,
+    buffer: Vec<i16>,
+}
+
+impl Crossfade {
+    pub fn new(player: Box<dyn MusixPlayer>, msec: usize) -> Self {
+        Self {
+            player,
+            fade: Fade::new(msec),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Mix the fading song into the interleaved stereo `samples` of the
+    /// next song, played at `freq` Hz. Returns true once the fading song is
+    /// done. Songs played at different frequencies are not mixed.
+    pub fn mix(&mut self, samples: &mut [f32], freq: u32) -> bool {
+        if self.player.get_frequency() != freq {
+            return true;
+        }
+        self.buffer.resize(samples.len(), 0);
+        let rc = self.player.get_samples(&mut self.buffer);
+        if rc == 0 {
+            return true;
+        }
+        let step = step(freq);
+        for (pair, old) in samples
+            .chunks_exact_mut(2)
+            .zip(self.buffer[..rc].chunks_exact(2))
+        {
+            let gain = self.fade.next_gain(step);
+            for (s, &o) in pair.iter_mut().zip(old) {
+                *s = *s * (1.0 - gain) + f32::from(o) / 32767.0 * gain;
+            }
+        }
+        self.fade.is_done()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use musix::MusixPlayer;
+
+    use super::{Crossfade, Fade, Ramp};
+
+    /// Plays a constant level forever
+    struct Constant(Vec<PathBuf>);
+
+    impl MusixPlayer for Constant {
+        fn get_song_files(&self) -> &Vec<PathBuf> {
+            &self.0
+        }
+
+        fn get_frequency(&self) -> u32 {
+            2000
+        }
+
+        fn get_samples(&mut self, target: &mut [i16]) -> usize {
+            target.fill(32767);
+            target.len()
+        }
+    }
+
+    #[test]
+    fn audio_fades_out() {
+        let mut fade = Fade::new(2);
+        let mut samples = [1.0; 8];
+        // Two stereo samples per millisecond
+        assert!(!fade.apply(&mut samples[..4], 2000));
+        assert_eq!(samples[..4], [1.0, 1.0, 0.75, 0.75]);
+        assert!(fade.apply(&mut samples[4..], 2000));
+        assert_eq!(samples[4..], [0.5, 0.5, 0.25, 0.25]);
+        let mut more = [1.0; 2];
+        assert!(fade.apply(&mut more, 2000));
+        assert_eq!(more, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn volume_is_ramped() {
+        let mut ramp = Ramp::new(0.5);
+        let mut samples = [1.0; 4];
+        ramp.apply(&mut samples, 2000);
+        assert_eq!(samples, [0.5; 4]);
+        // A tenth of the volume takes 5 msec, or 10 stereo samples at 2 kHz
+        ramp.set(0.6);
+        let mut samples = [1.0; 80];
+        ramp.apply(&mut samples, 2000);
+        assert!(samples[0] > 0.5 && samples[0] < 0.6);
+        assert!((samples[38] - 0.6).abs() < 1e-4);
+        assert_eq!(samples[78], 0.6);
+    }
+
+    #[test]
+    fn songs_are_crossfaded() {
+        let mut crossfade = Crossfade::new(Box::new(Constant(Vec::new())), 2);
+        let mut samples = [0.0; 4];
+        assert!(!crossfade.mix(&mut samples, 2000));
+        assert_eq!(samples, [1.0, 1.0, 0.75, 0.75]);
+        let mut samples = [1.0; 4];
+        assert!(crossfade.mix(&mut samples, 2000));
+        assert_eq!(samples, [1.0; 4]);
+        // Not mixed when played at another frequency
+        let mut samples = [0.5; 4];
+        assert!(crossfade.mix(&mut samples, 44100));
+        assert_eq!(samples, [0.5; 4]);
+    }
+}
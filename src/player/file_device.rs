@@ -0,0 +1,93 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::audio_device::{AudioCallback, AudioDevice};
+use crate::audio_writer::create_writer;
+use crate::log;
+
+const BUFFER_SIZE: usize = 2048;
+const PLAYBACK_FREQ_HZ: u32 = 44100;
+/// How often the file is made playable, in case we are killed
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Audio device that writes everything the audio callback produces to a file
+/// (or stdout), paced in real time like a sound card.
+pub(crate) struct FileDevice {
+    path: PathBuf,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FileDevice {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            running: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        }
+    }
+}
+
+impl Drop for FileDevice {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+impl AudioDevice for FileDevice {
+    fn play(
+        &mut self,
+        mut callback: AudioCallback,
+        _device_latency_us: Arc<AtomicUsize>,
+    ) -> Result<()> {
+        let mut writer = create_writer(&self.path, PLAYBACK_FREQ_HZ)?;
+        let running = self.running.clone();
+        running.store(true, Ordering::Relaxed);
+
+        self.thread = Some(thread::spawn(move || {
+            let mut buffer = vec![0.0f32; BUFFER_SIZE];
+            let start = Instant::now();
+            let mut last_sync = start;
+            let mut frames: u64 = 0;
+            while running.load(Ordering::Relaxed) {
+                callback(&mut buffer);
+                if let Err(e) = writer.write(&buffer) {
+                    log!("Could not write audio: {e}");
+                    break;
+                }
+                if last_sync.elapsed() >= SYNC_INTERVAL {
+                    last_sync = Instant::now();
+                    if let Err(e) = writer.sync() {
+                        log!("Could not sync audio file: {e}");
+                    }
+                }
+                // Sleep until the wall clock catches up with the audio written
+                frames += (BUFFER_SIZE / 2) as u64;
+                let due =
+                    start + Duration::from_micros(frames * 1_000_000 / u64::from(PLAYBACK_FREQ_HZ));
+                if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                    thread::sleep(wait);
+                }
+            }
+            if let Err(e) = writer.finish() {
+                log!("Could not finish audio file: {e}");
+            }
+        }));
+        Ok(())
+    }
+
+    fn get_buffer_size(&self) -> usize {
+        BUFFER_SIZE
+    }
+
+    fn get_playback_freq(&self) -> u32 {
+        PLAYBACK_FREQ_HZ
+    }
+}
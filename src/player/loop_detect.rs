@@ -0,0 +1,232 @@
+/// Fingerprint frames per second
+const FRAMES_PER_SEC: usize = 50;
+/// Shortest loop looked for
+const MIN_LOOP: usize = 5 * FRAMES_PER_SEC;
+/// Frames kept, which is also the longest loop that can be found
+const MAX_FRAMES: usize = 6 * 60 * FRAMES_PER_SEC;
+/// Frames compared when looking for a loop
+const WINDOW: usize = 4 * FRAMES_PER_SEC;
+/// How long the audio must keep repeating before it counts as a loop
+const CONFIRM: usize = 30 * FRAMES_PER_SEC;
+/// Mean difference in dB for two stretches of audio to count as the same
+const MAX_DIFF: f32 = 1.5;
+/// Windows with less variation than this (in dB) are too plain to compare
+const MIN_SPREAD: f32 = 3.0;
+const SILENT_DB: f32 = -90.0;
+
+/// Finds out when a song starts over, by looking for audio that repeats
+/// what was played before. Each frame of the fingerprint is the level of
+/// the audio and of its high frequencies, in dB.
+#[derive(Default, Debug)]
+pub struct LoopDetector {
+    /// Number of times to play the looping part, 0 = don't look for loops
+    loops: usize,
+    enabled: bool,
+    frames: Vec<[f32; 2]>,
+    /// Frames seen, including those not kept
+    position: usize,
+    /// Sum of squares of the samples and of their differences
+    energy: [f64; 2],
+    count: usize,
+    last: f32,
+    /// Length of a possible loop, and where it started to repeat
+    candidate: Option<(usize, usize)>,
+    /// Frame where the song should end, once a loop is found
+    end: Option<usize>,
+}
+
+fn db(power: f64) -> f32 {
+    if power <= 0.0 {
+        return SILENT_DB;
+    }
+    ((10.0 * power.log10()) as f32).max(SILENT_DB)
+}
+
+impl LoopDetector {
+    pub fn new(loops: usize) -> Self {
+        Self {
+            loops,
+            enabled: loops > 0,
+            ..Self::default()
+        }
+    }
+
+    /// Start over for a new song
+    pub fn reset(&mut self) {
+        *self = Self::new(self.loops);
+    }
+
+    /// Stop looking for loops in this song, as it has a known length
+    pub fn disable(&mut self) {
+        self.enabled = false;
+    }
+
+    /// Add the interleaved stereo `samples` played at `freq` Hz. Returns
+    /// true once the song has looped the wanted number of times.
+    pub fn update(&mut self, samples: &[f32], freq: u32) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let frame_len = (freq as usize / FRAMES_PER_SEC).max(1);
+        for pair in samples.chunks_exact(2) {
+            let mono = (pair[0] + pair[1]) / 2.0;
+            let diff = mono - self.last;
+            self.last = mono;
+            self.energy[0] += f64::from(mono * mono);
+            self.energy[1] += f64::from(diff * diff);
+            self.count += 1;
+            if self.count >= frame_len {
+                let n = self.count as f64;
+                self.add_frame([db(self.energy[0] / n), db(self.energy[1] / n)]);
+                self.energy = [0.0; 2];
+                self.count = 0;
+            }
+        }
+        self.end.is_some_and(|end| self.position >= end)
+    }
+
+    fn add_frame(&mut self, frame: [f32; 2]) {
+        self.position += 1;
+        if self.frames.len() >= MAX_FRAMES || self.end.is_some() {
+            return;
+        }
+        self.frames.push(frame);
+        if self.frames.len().is_multiple_of(FRAMES_PER_SEC) {
+            self.analyze();
+        }
+    }
+
+    /// Mean difference between the `len` frames ending at `end` and those
+    /// `lag` frames before them, or None if more than `max`
+    fn distance(&self, end: usize, lag: usize, len: usize, max: f32) -> Option<f32> {
+        let limit = max * len as f32;
+        let mut sum = 0.0;
+        for i in end - len..end {
+            let (a, b) = (self.frames[i], self.frames[i - lag]);
+            sum += ((a[0] - b[0]).abs() + (a[1] - b[1]).abs()) / 2.0;
+            if sum > limit {
+                return None;
+            }
+        }
+        Some(sum / len as f32)
+    }
+
+    fn analyze(&mut self) {
+        let n = self.frames.len();
+        if let Some((lag, start)) = self.candidate {
+            if self.distance(n, lag, FRAMES_PER_SEC, MAX_DIFF).is_none() {
+                self.candidate = None;
+            } else if n - start >= CONFIRM {
+                self.found_loop(lag, start);
+            }
+            return;
+        }
+        if n < MIN_LOOP + WINDOW {
+            return;
+        }
+        let window = &self.frames[n - WINDOW..];
+        let (min, max) = window.iter().fold((f32::MAX, f32::MIN), |(min, max), f| {
+            (min.min(f[0]), max.max(f[0]))
+        });
+        if max - min < MIN_SPREAD {
+            return;
+        }
+        let mut best: Option<(usize, f32)> = None;
+        for lag in MIN_LOOP..=n - WINDOW {
+            let max = best.map_or(MAX_DIFF, |(_, d)| d);
+            if let Some(d) = self.distance(n, lag, WINDOW, max)
+                && best.is_none_or(|(_, best)| d < best)
+            {
+                best = Some((lag, d));
+            }
+        }
+        let Some((mut lag, _)) = best else {
+            return;
+        };
+        // A song that loops also matches itself two or three loops back
+        for parts in [3, 2] {
+            let part = lag / parts;
+            if part >= MIN_LOOP
+                && (part - 1..=part + 1).any(|l| self.distance(n, l, WINDOW, MAX_DIFF).is_some())
+            {
+                lag = part;
+                break;
+            }
+        }
+        self.candidate = Some((lag, n - WINDOW));
+    }
+
+    /// The audio has repeated for long enough. Find out where the repeat
+    /// started, and when the song should end.
+    fn found_loop(&mut self, lag: usize, mut start: usize) {
+        while start >= lag + FRAMES_PER_SEC
+            && self
+                .distance(start, lag, FRAMES_PER_SEC, MAX_DIFF)
+                .is_some()
+        {
+            start -= FRAMES_PER_SEC;
+        }
+        let end = start + (self.loops - 1) * lag;
+        self.end = Some(end.max(self.frames.len()));
+        self.candidate = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LoopDetector;
+
+    const FREQ: u32 = 10000;
+
+    /// Notes of a quarter second each, made up from `seed`
+    fn tune(seed: u32, secs: f32) -> Vec<f32> {
+        let mut rng = seed;
+        let mut samples = Vec::new();
+        let len = (secs * FREQ as f32) as usize;
+        let note_len = FREQ as usize / 4;
+        while samples.len() < len * 2 {
+            rng = rng.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let pitch = 100.0 + (rng >> 16) as f32 % 800.0;
+            let volume = 0.05 + (rng >> 8) as f32 % 100.0 / 200.0;
+            for i in 0..note_len.min(len - samples.len() / 2) {
+                let t = i as f32 / FREQ as f32;
+                let s = (t * pitch * std::f32::consts::TAU).sin() * volume;
+                samples.extend([s, s]);
+            }
+        }
+        samples
+    }
+
+    /// Seconds played until the detector says the song is done
+    fn play(detector: &mut LoopDetector, intro: &[f32], body: &[f32]) -> Option<f32> {
+        let mut song = intro.to_vec();
+        while song.len() < 200 * FREQ as usize * 2 {
+            song.extend_from_slice(body);
+        }
+        for (i, chunk) in song.chunks(1000).enumerate() {
+            if detector.update(chunk, FREQ) {
+                return Some((i * 1000) as f32 / (FREQ as f32 * 2.0));
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn loops_are_found() {
+        let intro = tune(1, 3.0);
+        let body = tune(2, 8.013);
+        let mut detector = LoopDetector::new(8);
+        let secs = play(&mut detector, &intro, &body).unwrap();
+        // Intro, then the loop eight times
+        let expected = 3.0 + 8.013 * 8.0;
+        assert!((secs - expected).abs() < 1.5, "{secs} != {expected}");
+
+        detector.reset();
+        detector.disable();
+        assert_eq!(play(&mut detector, &intro, &body), None);
+
+        let mut detector = LoopDetector::new(2);
+        let secs = play(&mut detector, &intro, &tune(3, 200.0)).unwrap_or(0.0);
+        assert_eq!(secs, 0.0, "No loop in the song, but ended at {secs}");
+    }
+}
@@ -0,0 +1,111 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+use musix::{MusicError, MusixPlayer};
+
+use crate::archive;
+
+/// The file to load for the song `name`. Songs inside archives are unpacked
+/// to a temporary directory first.
+pub fn song_file(name: &Path) -> Result<PathBuf, MusicError> {
+    if archive::split(name).is_some() {
+        archive::extract(name).map_err(|e| MusicError { msg: e.to_string() })
+    } else {
+        Ok(name.to_owned())
+    }
+}
+
+/// A song loaded in the background
+pub struct Preloaded {
+    /// The file the song was loaded from
+    pub file: PathBuf,
+    /// The loaded song, if its plugin allows it to be loaded while another
+    /// song plays
+    pub player: Option<Box<dyn MusixPlayer>>,
+}
+
+/// A player moved from the preload thread to the audio thread
+struct SentPlayer(Box<dyn MusixPlayer>);
+
+// SAFETY: `musix::load_song` only returns `ChipPlayer` and `FlacPlayer`,
+// which are both `Send`. The box just loses that in its type.
+unsafe impl Send for SentPlayer {}
+
+type PreloadResult = Result<(PathBuf, Option<SentPlayer>), MusicError>;
+
+/// Loads the song expected next in a background thread, so that it starts
+/// without a gap when the current song ends.
+///
+/// Songs are only loaded if `load_player` is set when asked for, which is
+/// when their plugin can have more than one song loaded (see
+/// `can_crossfade()`). For other songs, like those played by UADE, only the
+/// file is unpacked, and the song is loaded on the audio thread after the
+/// current one is unloaded.
+pub struct Preloader {
+    requests: mpsc::Sender<(PathBuf, bool)>,
+    results: mpsc::Receiver<(PathBuf, PreloadResult)>,
+    /// The song asked for last
+    wanted: Option<PathBuf>,
+}
+
+impl Preloader {
+    pub fn start() -> Self {
+        let (requests, request_receiver) = mpsc::channel::<(PathBuf, bool)>();
+        let (result_sender, results) = mpsc::channel();
+        // Ends when the preloader is dropped
+        thread::spawn(move || {
+            while let Ok(mut request) = request_receiver.recv() {
+                // Only the latest request matters
+                while let Ok(newer) = request_receiver.try_recv() {
+                    request = newer;
+                }
+                let (name, load_player) = request;
+                let result = song_file(&name).and_then(|file| {
+                    let player = if load_player {
+                        Some(SentPlayer(musix::load_song(&file)?))
+                    } else {
+                        None
+                    };
+                    Ok((file, player))
+                });
+                if result_sender.send((name, result)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            requests,
+            results,
+            wanted: None,
+        }
+    }
+
+    /// Start getting the song `name` ready, unless it already is. The song
+    /// itself is loaded too if `load_player` is set.
+    pub fn preload(&mut self, name: &Path, load_player: bool) {
+        if self.wanted.as_deref() != Some(name)
+            && self.requests.send((name.to_owned(), load_player)).is_ok()
+        {
+            self.wanted = Some(name.to_owned());
+        }
+    }
+
+    /// The song `name` if it was preloaded, waiting for it if it is not
+    /// ready yet
+    pub fn take(&mut self, name: &Path) -> Option<Result<Preloaded, MusicError>> {
+        if self.wanted.take().as_deref() != Some(name) {
+            return None;
+        }
+        // Results for songs asked for earlier may come first
+        self.results
+            .iter()
+            .find(|(path, _)| path == name)
+            .map(|(_, result)| {
+                result.map(|(file, player)| Preloaded {
+                    file,
+                    player: player.map(|p| p.0),
+                })
+            })
+    }
+}
@@ -0,0 +1,68 @@
+/// Finds out when a song has been silent for a while, since many songs end
+/// in minutes of silence instead of stopping
+#[derive(Default, Debug)]
+pub struct Silence {
+    /// RMS level below which audio counts as silence
+    level: f32,
+    /// How long the audio must be silent, 0 = never
+    max_msec: usize,
+    silent_msec: usize,
+}
+
+impl Silence {
+    /// Silence is audio below `level_db` dB for `seconds` seconds
+    pub fn new(level_db: f64, seconds: f64) -> Self {
+        Self {
+            level: 10f64.powf(level_db / 20.0) as f32,
+            max_msec: (seconds.max(0.0) * 1000.0) as usize,
+            silent_msec: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.silent_msec = 0;
+    }
+
+    /// Add the interleaved stereo `samples` played at `freq` Hz. Returns
+    /// true once the audio has been silent for long enough.
+    pub fn update(&mut self, samples: &[f32], freq: u32) -> bool {
+        if self.max_msec == 0 || samples.is_empty() || freq == 0 {
+            return false;
+        }
+        // Measured around the mean, as some chips output a constant level
+        // when silent
+        let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+        let power = samples.iter().map(|s| (s - mean) * (s - mean)).sum::<f32>();
+        let rms = (power / samples.len() as f32).sqrt();
+        if rms < self.level {
+            self.silent_msec += samples.len() * 1000 / (freq as usize * 2);
+        } else {
+            self.silent_msec = 0;
+        }
+        self.silent_msec >= self.max_msec
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Silence;
+
+    #[test]
+    fn silence_is_found() {
+        // 100 msec of stereo audio at 10 kHz
+        let quiet = vec![0.3; 2000];
+        let loud: Vec<f32> = (0..2000).map(|i| (i as f32 * 0.1).sin() * 0.5).collect();
+        let mut silence = Silence::new(-60.0, 0.25);
+        assert!(!silence.update(&quiet, 10000));
+        assert!(!silence.update(&quiet, 10000));
+        assert!(!silence.update(&loud, 10000));
+        assert!(!silence.update(&quiet, 10000));
+        assert!(!silence.update(&quiet, 10000));
+        assert!(silence.update(&quiet, 10000));
+        silence.reset();
+        assert!(!silence.update(&quiet, 10000));
+
+        let mut never = Silence::default();
+        assert!(!never.update(&quiet, 10000));
+    }
+}
@@ -0,0 +1,142 @@
+use anyhow::{Context, Result};
+
+use crate::RenderArgs;
+use crate::audio_writer::{SampleWriter, create_writer};
+use crate::log;
+use crate::player::init_music;
+use crate::resampler::Resampler;
+
+const RENDER_BUFFER_SIZE: usize = 4096;
+/// Used when the song length is not known and no length was given
+const DEFAULT_RENDER_SECONDS: f64 = 180.0;
+
+/// Resample and write the `pending` samples, at most `left` of them.
+/// When resampling, only full buffers are written, and the rest is kept for
+/// later unless this is the `last` write, where the final buffer is padded.
+/// Returns the number of samples written.
+fn write_samples(
+    resampler: &mut Resampler,
+    writer: &mut dyn SampleWriter,
+    pending: &mut Vec<f32>,
+    resampling: bool,
+    last: bool,
+    left: usize,
+) -> Result<usize> {
+    if !resampling {
+        let n = pending.len().min(left);
+        writer.write(&pending[..n])?;
+        pending.clear();
+        return Ok(n);
+    }
+    if last {
+        pending.resize(pending.len().next_multiple_of(RENDER_BUFFER_SIZE), 0.0);
+    }
+    let mut written = 0;
+    let full = pending.len() - pending.len() % RENDER_BUFFER_SIZE;
+    for chunk in pending[..full].chunks_exact(RENDER_BUFFER_SIZE) {
+        let new_samples = resampler.process(chunk)?;
+        let n = new_samples.len().min(left - written);
+        writer.write(&new_samples[..n])?;
+        written += n;
+    }
+    pending.drain(..full);
+    Ok(written)
+}
+
+/// Render a song to an audio file, as fast as the plugin can produce samples.
+///
+/// Renders for `seconds` if given, otherwise for the length reported by the
+/// song meta data, and stops early if the song ends.
+pub fn render(args: &RenderArgs) -> Result<()> {
+    init_music();
+    let mut chip_player = musix::load_song(&args.song)
+        .with_context(|| format!("Could not load {}", args.song.display()))?;
+    if let Some(subtune) = args.subtune {
+        // Same numbering as `Player::set_song`
+        chip_player.seek(subtune - 1, 0);
+    }
+
+    let mut length: Option<f64> = None;
+    if let Some(ext) = args.song.extension()
+        && ext == "mp3"
+        && let Ok(duration) = mp3_duration::from_path(&args.song)
+    {
+        length = Some(duration.as_secs_f64());
+    }
+
+    let mut writer = create_writer(&args.output, args.freq)?;
+    let mut resampler = Resampler::new(RENDER_BUFFER_SIZE / 2)?;
+    let mut plugin_freq = args.freq;
+    let mut target: Vec<i16> = vec![0; RENDER_BUFFER_SIZE];
+    // Samples waiting for a full buffer to resample
+    let mut pending: Vec<f32> = Vec::new();
+    let mut written = 0;
+    let mut total;
+
+    loop {
+        // Song length may change after seek, and is often only known after
+        // the first samples are generated
+        while let Some(meta) = chip_player.get_changed_meta() {
+            if meta == "length"
+                && let Some(secs) = chip_player
+                    .get_meta_string(&meta)
+                    .and_then(|l| l.parse::<f64>().ok())
+                && secs > 0.0
+            {
+                length = Some(secs);
+            }
+        }
+        let seconds = args.seconds.or(length).unwrap_or(DEFAULT_RENDER_SECONDS);
+        total = (seconds * f64::from(args.freq)) as usize * 2;
+        if written >= total {
+            break;
+        }
+
+        let rc = chip_player.get_samples(&mut target);
+        if rc == 0 {
+            break;
+        }
+        let hz = chip_player.get_frequency();
+        if hz != plugin_freq {
+            log!("Plugin freq: {hz}");
+            // Samples at the old frequency are written first
+            written += write_samples(
+                &mut resampler,
+                writer.as_mut(),
+                &mut pending,
+                plugin_freq != args.freq,
+                true,
+                total - written,
+            )?;
+            plugin_freq = hz;
+            resampler.set_frequencies(plugin_freq, args.freq)?;
+        }
+
+        pending.extend(target[..rc].iter().map(|&s16| f32::from(s16) / 32767.0));
+        written += write_samples(
+            &mut resampler,
+            writer.as_mut(),
+            &mut pending,
+            plugin_freq != args.freq,
+            false,
+            total - written,
+        )?;
+    }
+    // The last samples, padded to fill the resampler
+    written += write_samples(
+        &mut resampler,
+        writer.as_mut(),
+        &mut pending,
+        plugin_freq != args.freq,
+        true,
+        total.saturating_sub(written),
+    )?;
+    writer.finish()?;
+
+    println!(
+        "Wrote {} ({:.1}s)",
+        args.output.display(),
+        written as f64 / (f64::from(args.freq) * 2.0)
+    );
+    Ok(())
+}
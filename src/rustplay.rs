@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use crossterm::event::KeyEvent;
 use crossterm::style::SetBackgroundColor;
 use gui::KeyReturn;
@@ -8,20 +8,22 @@ use std::fmt::Display;
 use std::io::{self, Write as _, stdout};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, mpsc};
-use std::time::Instant;
-use std::{fs, panic, thread::JoinHandle};
+use std::time::{Duration, Instant};
+use std::{panic, thread::JoinHandle};
 
 use crate::media_keys::{self, MediaKeyEvent, MediaKeyInfo};
-use crate::player::{Cmd, Info, PlayResult, PlayState, Player, init_music};
+use crate::player::{
+    Cmd, CpalBackend, FileBackend, Info, PlayResult, PlayState, Player, can_crossfade, init_music,
+};
 use crate::rustplay::gui::MenuNav;
 use crate::rustplay::indexer::SongIndexer;
 use crate::rustplay::state::Msg;
 use crate::templ::Template;
 use crate::utils::make_color;
 use crate::value::Value;
-use crate::{Args, CONFIG_LUA, log};
+use crate::{Args, CONFIG_LUA, DEFAULT_SUBTUNE_TIMEOUT, Settings, archive, log};
 use crossterm::{
     QueueableCommand, cursor,
     event::{self, Event, KeyCode},
@@ -31,25 +33,75 @@ use crossterm::{
 };
 
 mod gui;
+mod history;
 mod indexer;
+mod play_order;
+mod play_queue;
+mod playlist;
+mod saved_playlists;
 mod scripting;
 mod song;
+mod song_lengths;
+mod sort_order;
 mod state;
+mod volume;
+mod watcher;
 
 use crate::term_extra::{MaybeCommand, SetReverse};
 
 use song::{FileInfo, FileType, SongArray, SongCollection};
+use sort_order::SortOrder;
 
+use history::{History, HistoryEntry};
 use indexer::RemoteSongIndexer;
+use play_order::{PlayMode, PlayOrder};
+use play_queue::PlayQueue;
+use saved_playlists::Playlists;
 use state::{InputMode, State};
+use volume::Volume;
+
+/// How long typing must pause before the search preview is updated
+const SEARCH_DELAY: Duration = Duration::from_millis(150);
+
+/// Max number of songs shown in the search preview
+const PREVIEW_LIMIT: usize = 500;
+
+/// Set by SIGINT and SIGTERM, so that we can quit cleanly without a terminal
+static QUIT_SIGNALED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_quit_signal(_signal: libc::c_int) {
+    QUIT_SIGNALED.store(true, Ordering::SeqCst);
+}
+
+/// Quit on SIGINT and SIGTERM instead of being killed, so audio files are
+/// finished properly
+#[cfg(unix)]
+fn catch_quit_signals() {
+    let handler = on_quit_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+#[cfg(not(unix))]
+fn catch_quit_signals() {}
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
 enum MenuId {
     Search,
     Favorites,
     Dir,
+    Queue,
+    History,
+    Playlists,
 }
 
+/// The playlist `~/.opfavorites` is imported into, shown until another
+/// playlist is picked
+const FAVORITES: &str = "Favorites";
+
 /// The RustPlay application
 pub struct RustPlay {
     cmd_producer: mpsc::Sender<Cmd>,
@@ -64,14 +116,32 @@ pub struct RustPlay {
     menus: HashMap<MenuId, gui::SongMenu>,
     current_menu: MenuId,
     search_component: gui::SearchField,
+    search_preview: gui::SearchPreview,
     fft_component: gui::Fft,
     fft_queue: VecDeque<(Instant, Vec<u8>)>,
     current_playlist: Rc<dyn SongCollection>,
     current_song: usize,
+    play_order: PlayOrder,
+    playing_all_songs: bool,
+    queue: PlayQueue,
+    /// Set when the playing song is not from the current playlist
+    /// (taken from the queue or the history)
+    detached_song: Option<FileInfo>,
+    history: History,
     scripting: Option<Scripting>,
     media_keys_receiver: mpsc::Receiver<MediaKeyEvent>,
     media_sender: mpsc::Sender<MediaKeyInfo>,
-    favorites_dir: PathBuf,
+    playlists: Playlists,
+    /// The playlist shown in the favorites menu, and that songs are added to
+    open_playlist: String,
+    settings: Settings,
+    volume: Volume,
+    /// How long to play the playing song if it has no known length, 0 = until it ends
+    play_time_msec: usize,
+    /// The end of the playing song has started, by fading or crossfading
+    song_ending: bool,
+    /// Crossfade time to use when the next song is loaded
+    pending_crossfade: Option<usize>,
     start_dir: PathBuf,
     current_dir: PathBuf,
 }
@@ -88,18 +158,25 @@ impl RustPlay {
         let (info_producer, info_consumer) = mpsc::channel::<Info>();
         let msec = Arc::new(AtomicUsize::new(0));
 
-        let (media_sender, media_keys_receiver) = media_keys::start();
+        let (media_sender, media_keys_receiver) = media_keys::start(msec.clone());
 
-        if !args.no_term {
+        // Raw PCM on stdout can not share it with the terminal UI
+        let no_term = args.no_term || args.audio_file.as_deref() == Some(Path::new("-"));
+        if no_term {
+            catch_quit_signals();
+        } else {
             Self::setup_term()?;
         }
 
-        let (w, h) = terminal::size()?;
+        let (w, h) = match terminal::size() {
+            Err(_) if no_term => (80, 25),
+            size => size?,
+        };
 
-        let script_path = dirs::config_dir()
+        let config_dir = dirs::config_dir()
             .map(|d| d.join("oldplay"))
-            .unwrap_or_default()
-            .join("config.lua");
+            .unwrap_or_default();
+        let script_path = config_dir.join("config.lua");
 
         let script = if script_path.is_file() {
             std::fs::read_to_string(&script_path)?
@@ -120,37 +197,77 @@ impl RustPlay {
 
         init_music();
 
-        let indexer = RemoteSongIndexer::new()?;
-        indexer.ignore_cache(args.ignore_cache)?;
-
         let home_dir = dirs::home_dir().expect("User should have a home dir");
-        let mut start_dir = home_dir.clone();
+        let start_dir: PathBuf = match args.songs.last() {
+            Some(song) if song.is_file() => song.parent().unwrap_or(Path::new("")).into(),
+            Some(song) => song.into(),
+            None => home_dir.clone(),
+        };
 
-        if args.songs.is_empty() {
-            let test_song: PathBuf = "music.mod".into();
-            if test_song.is_file() {
-                indexer.add_path(&test_song)?;
-            }
+        let (playlist_files, roots): (Vec<PathBuf>, Vec<PathBuf>) = args
+            .songs
+            .iter()
+            .cloned()
+            .partition(|p| playlist::is_playlist(p));
+        let test_song: PathBuf = "music.mod".into();
+        let roots = if args.songs.is_empty() && test_song.is_file() {
+            vec![test_song]
         } else {
-            for song in &args.songs {
-                if song.is_file() {
-                    start_dir = song.parent().unwrap_or(Path::new("")).into();
-                } else {
-                    start_dir = song.into();
-                }
-                indexer.add_path(song)?;
-            }
+            roots
+        };
+        let mut playlist_songs = Vec::new();
+        for file in &playlist_files {
+            let songs = playlist::load(file, SongIndexer::identify_song)
+                .with_context(|| format!("Could not read playlist {file:?}"))?;
+            playlist_songs.extend(songs);
         }
 
-        let current_list = indexer.get_all_songs();
-        let favorites_dir = home_dir.join(".opfavorites");
+        let indexer = RemoteSongIndexer::open(&roots)?;
+        indexer.ignore_cache(args.ignore_cache)?;
+        indexer.set_fuzzy(settings.fuzzy_search);
+        for song in &roots {
+            indexer.add_path(song)?;
+        }
+
+        // Play the playlists given, or else all songs
+        let current_list: Rc<dyn SongCollection> = if playlist_files.is_empty() {
+            indexer.get_all_songs()
+        } else {
+            Rc::new(SongArray {
+                songs: playlist_songs,
+            })
+        };
         let audio_delay_us = Arc::new(AtomicUsize::new(0));
 
-        let songs = Self::load_favorites(&favorites_dir);
+        let mut playlists = Playlists::load(config_dir.join("playlists"));
+        // Favorites used to be copied to a directory of their own
+        let favorites_dir = home_dir.join(".opfavorites");
+        if playlists.get(FAVORITES).is_none() && favorites_dir.is_dir() {
+            match playlists.import_favorites(&favorites_dir, FAVORITES) {
+                Ok(count) => log!("Imported {count} favorites from {favorites_dir:?}"),
+                Err(e) => log!("Could not import favorites: {e}"),
+            }
+        }
+        let songs = playlists
+            .get(FAVORITES)
+            .map(|list| list.songs(SongIndexer::identify_song))
+            .unwrap_or_default();
         let mut fav_menu = gui::SongMenu::new(use_color, w.into(), h.into());
-        fav_menu.set_songs("Favorites", Rc::new(SongArray { songs }));
+        fav_menu.set_songs(FAVORITES, Rc::new(SongArray { songs }));
+
+        let history = History::load(config_dir.join("history.toml"));
+        let mut history_menu = gui::SongMenu::new(use_color, w.into(), h.into());
+        history_menu.set_songs(
+            "History",
+            Rc::new(SongArray {
+                songs: history.songs(),
+            }),
+        );
 
         let (sx, sy) = templ.get_pos("search").unwrap_or((1, (th + 1) as u16));
+        let mut search_preview = gui::SearchPreview::new(sx, sy + 1, use_color);
+        search_preview.width = w.into();
+        search_preview.height = usize::from(h).saturating_sub(usize::from(sy) + 2);
 
         let height = settings.fft.visualizer_height as i32;
         let fft_component = gui::Fft {
@@ -164,19 +281,41 @@ impl RustPlay {
             colors: gui::interpolate_colors(&settings.fft.colors, height as usize),
         };
 
-        Ok(RustPlay {
-            cmd_producer,
-            info_consumer,
-            templ,
-            msec: msec.clone(),
-            player_thread: Some(crate::player::run_player(
+        // Files are rendered at full volume
+        let volume = if args.audio_file.is_some() {
+            Volume::default()
+        } else {
+            Volume::load(config_dir.join("volume.toml"))
+        };
+        let player_thread = if let Some(path) = args.audio_file {
+            crate::player::run_player(
                 &settings,
                 info_producer,
                 cmd_consumer,
-                msec,
+                msec.clone(),
                 audio_delay_us,
-                crate::player::CpalBackend,
-            )?),
+                FileBackend { path },
+            )?
+        } else {
+            crate::player::run_player(
+                &settings,
+                info_producer,
+                cmd_consumer,
+                msec.clone(),
+                audio_delay_us,
+                CpalBackend,
+            )?
+        };
+        let gain = volume.gain();
+        let _ = cmd_producer.send(Box::new(move |player: &mut Player| player.set_volume(gain)));
+        let _ = media_sender.send(MediaKeyInfo::Volume(gain));
+
+        Ok(RustPlay {
+            cmd_producer,
+            info_consumer,
+            templ,
+            msec,
+            player_thread: Some(player_thread),
             state: State {
                 changed: true,
                 use_color: !args.no_color,
@@ -185,7 +324,7 @@ impl RustPlay {
                 ..State::default()
             },
             height: h.into(),
-            no_term: args.no_term,
+            no_term,
             indexer,
             menus: HashMap::from([
                 (
@@ -197,18 +336,39 @@ impl RustPlay {
                     MenuId::Dir,
                     gui::SongMenu::new(use_color, w.into(), h.into()),
                 ),
+                (
+                    MenuId::Queue,
+                    gui::SongMenu::new(use_color, w.into(), h.into()),
+                ),
+                (MenuId::History, history_menu),
+                (
+                    MenuId::Playlists,
+                    gui::SongMenu::new(use_color, w.into(), h.into()),
+                ),
             ]),
             current_menu: MenuId::Dir,
             search_component: gui::SearchField::new(sx, sy, use_color),
+            search_preview,
             fft_component,
             fft_queue: VecDeque::new(),
             current_playlist: current_list.clone(),
             current_song: 0,
+            play_order: PlayOrder::default(),
+            playing_all_songs: playlist_files.is_empty(),
+            queue: PlayQueue::default(),
+            detached_song: None,
+            history,
             scripting,
             media_keys_receiver,
             media_sender,
-            favorites_dir,
+            playlists,
+            open_playlist: FAVORITES.to_owned(),
             current_dir: start_dir.clone(),
+            settings,
+            volume,
+            play_time_msec: 0,
+            song_ending: false,
+            pending_crossfade: None,
             start_dir,
         })
     }
@@ -323,9 +483,7 @@ impl RustPlay {
             self.state.player_started = true;
         }
         // TODO: Separate update() function for things like this
-        if self.state.len_msec > 0 && play_time > self.state.len_msec {
-            self.next_song();
-        }
+        self.check_song_end(play_time);
 
         if self.no_term {
             return Ok(());
@@ -350,6 +508,7 @@ impl RustPlay {
 
         if self.state.mode == InputMode::SearchInput {
             self.search_component.draw()?;
+            self.search_preview.draw()?;
         } else {
             let scripting = self.scripting.as_ref().expect("scripting should exist");
 
@@ -394,7 +553,10 @@ impl RustPlay {
                 break;
             }
         }
-        self.fft_component.draw()?;
+        if self.state.mode != InputMode::SearchInput {
+            // The search preview is drawn on top of the visualizer
+            self.fft_component.draw()?;
+        }
 
         if self.state.show_error > 0 {
             self.state.show_error -= 1;
@@ -437,6 +599,31 @@ impl RustPlay {
             .expect("Only fails when other end has quit");
     }
 
+    /// Search for the text in the search field once it has not changed
+    /// for a moment, and show the result under the field
+    fn update_search_preview(&mut self) {
+        if self.state.mode != InputMode::SearchInput {
+            return;
+        }
+        let text = self.search_component.text();
+        let Some(query) = self.search_preview.pending_query(&text, SEARCH_DELAY) else {
+            return;
+        };
+        let songs = if query.trim().is_empty() {
+            Vec::new()
+        } else {
+            match self.indexer.search_prefix(&query, PREVIEW_LIMIT) {
+                Ok(songs) => songs,
+                Err(e) => {
+                    // Half typed queries like `title:` are expected to fail
+                    log!("Search preview failed: {e}");
+                    return;
+                }
+            }
+        };
+        self.search_preview.set_songs(songs);
+    }
+
     fn search(&mut self, query: &str) -> Result<()> {
         log!("Searching for {}", query);
         let songs = self.indexer.search(query)?;
@@ -469,6 +656,18 @@ impl RustPlay {
         self.send_cmd(Player::prev_song);
     }
 
+    /// Seek `secs` seconds forward, or backward if negative
+    fn seek(&mut self, secs: f64) {
+        let msec = (secs * 1000.0) as i64;
+        self.send_cmd(move |player| player.seek_relative(msec));
+    }
+
+    /// Seek to `secs` seconds from the start of the song
+    fn seek_to(&mut self, secs: f64) {
+        let msec = (secs.max(0.0) * 1000.0) as usize;
+        self.send_cmd(move |player| player.seek(msec));
+    }
+
     fn handle_resize(&mut self, width: u16, height: u16) {
         self.state.width = width as i32;
         self.state.height = height as i32;
@@ -481,6 +680,9 @@ impl RustPlay {
         for m in self.menus.values_mut() {
             m.resize(width as usize, height as usize);
         }
+        self.search_preview.width = width as usize;
+        self.search_preview.height =
+            (height as usize).saturating_sub(usize::from(self.search_preview.ypos) + 1);
     }
 
     pub fn play_pause(&mut self) {
@@ -492,7 +694,8 @@ impl RustPlay {
         self.state.mode = InputMode::Main;
     }
 
-    fn show_directory(&mut self) -> Result<()> {
+    /// Read the contents of `current_dir` into the directory menu
+    fn update_dir_menu(&mut self) -> Result<()> {
         let songs = self.indexer.browse(&self.current_dir)?;
         let dir_menu = self.menus.get_mut(&MenuId::Dir).unwrap();
         let name = self
@@ -501,6 +704,11 @@ impl RustPlay {
             .to_string_lossy();
         dir_menu.set_songs(name, Rc::new(SongArray { songs }));
         //dir_menu.set_info(format!("\"{name}\""), "[/] = Parent");
+        Ok(())
+    }
+
+    fn show_directory(&mut self) -> Result<()> {
+        self.update_dir_menu()?;
         self.current_menu = MenuId::Dir;
         self.state.mode = InputMode::ResultScreen;
         Ok(())
@@ -522,9 +730,89 @@ impl RustPlay {
         if !self.menus[&MenuId::Favorites].songs().is_empty() {
             self.current_menu = MenuId::Favorites;
             self.state.mode = InputMode::ResultScreen;
-        } else {
+        } else if self.open_playlist == FAVORITES {
             self.state.info("No favorites yet");
+        } else {
+            self.state.info(format!("{} is empty", self.open_playlist));
+        }
+    }
+
+    /// Show all playlists, to pick the one to show and add songs to
+    pub fn show_playlists(&mut self) {
+        let lists: Vec<FileInfo> = self
+            .playlists
+            .lists()
+            .iter()
+            .map(|list| {
+                let mut info = FileInfo {
+                    path: PathBuf::from(&list.name),
+                    file_type: FileType::Dir,
+                    ..FileInfo::default()
+                };
+                info.meta_data
+                    .insert("title".into(), Value::Text(list.name.clone()));
+                info.meta_data.insert(
+                    "composer".into(),
+                    Value::Text(format!("{} songs", list.entries.len())),
+                );
+                info
+            })
+            .collect();
+        if lists.is_empty() {
+            self.state.info("No playlists yet");
+            return;
+        }
+        let open = lists
+            .iter()
+            .position(|l| l.path == Path::new(&self.open_playlist))
+            .unwrap_or(0);
+        let menu = self.get_menu(&MenuId::Playlists);
+        menu.set_songs("Playlists", Rc::new(SongArray { songs: lists }));
+        menu.select(open);
+        self.current_menu = MenuId::Playlists;
+        self.state.mode = InputMode::ResultScreen;
+    }
+
+    /// Make `name` the playlist shown as favorites
+    fn open_playlist(&mut self, name: &str) {
+        self.open_playlist = name.to_owned();
+        self.update_playlist_menu();
+        self.current_menu = MenuId::Favorites;
+        self.state.mode = InputMode::ResultScreen;
+        self.state.changed = true;
+    }
+
+    /// Create an empty playlist and open it
+    fn new_playlist(&mut self, name: &str) -> Result<()> {
+        self.playlists.create(name)?;
+        self.open_playlist(name);
+        Ok(())
+    }
+
+    fn update_playlist_menu(&mut self) {
+        let songs = self
+            .playlists
+            .get(&self.open_playlist)
+            .map(|list| list.songs(SongIndexer::identify_song))
+            .unwrap_or_default();
+        let name = self.open_playlist.clone();
+        let menu = self.get_menu(&MenuId::Favorites);
+        let selected = menu.selected;
+        menu.set_songs(name, Rc::new(SongArray { songs }));
+        menu.select(selected);
+    }
+
+    /// Remove the selected song from the open playlist
+    fn remove_from_playlist(&mut self) -> Result<()> {
+        if self.current_menu != MenuId::Favorites {
+            return Ok(());
         }
+        let selected = self.current_menu().selected_source();
+        let name = self.open_playlist.clone();
+        self.playlists.remove(&name, selected)?;
+        self.update_playlist_menu();
+        self.state.changed = true;
+        Ok(())
     }
 
     pub fn show_search_result(&mut self) -> bool {
@@ -556,6 +844,9 @@ impl RustPlay {
                 MenuId::Search => InputMode::SearchScreen,
                 MenuId::Favorites => InputMode::FavScreen,
                 MenuId::Dir => InputMode::DirScreen,
+                MenuId::Queue => InputMode::QueueScreen,
+                MenuId::History => InputMode::HistoryScreen,
+                MenuId::Playlists => InputMode::PlaylistsScreen,
             }
         } else {
             self.state.mode
@@ -565,19 +856,48 @@ impl RustPlay {
     fn enter_or_play_selected(&mut self) -> Result<()> {
         let menu = self.current_menu();
         let song = menu.get_current();
-        if song.file_type == FileType::Dir {
+        if self.current_menu == MenuId::Queue {
+            // Play the selected song now instead of later
+            let selected = self.current_menu().selected_source();
+            if let Some(song) = self.queue.remove(selected) {
+                self.update_queue_menu();
+                self.play_queued(song);
+            }
+            self.state.mode = InputMode::Main;
+        } else if self.current_menu == MenuId::History {
+            let selected = self.current_menu().selected_source();
+            if let Some(entry) = self.history.get_recent(selected).cloned() {
+                self.detached_song = Some(entry.to_file_info());
+                self.play_song(&entry.to_file_info());
+                self.set_subtune(entry.subtune);
+            }
+            self.state.changed = true;
+            self.state.mode = InputMode::Main;
+        } else if self.current_menu == MenuId::Playlists {
+            self.open_playlist(&song.path.to_string_lossy());
+        } else if song.file_type == FileType::Dir {
             self.current_dir = song.path;
             self.show_directory()?;
         } else {
-            self.current_playlist = self.current_menu().songs().clone();
-            self.current_song = self.current_playlist.index_of(&song).unwrap_or(0);
-            self.play_song(&song);
-            self.state.changed = true;
-            self.state.mode = InputMode::Main;
+            let songs = self.current_menu().songs().clone();
+            self.play_from_list(songs, &song);
         }
         Ok(())
     }
 
+    /// Play `song`, making `songs` the current playlist
+    fn play_from_list(&mut self, songs: Rc<dyn SongCollection>, song: &FileInfo) {
+        self.current_playlist = songs;
+        self.current_song = self.current_playlist.index_of(song).unwrap_or(0);
+        self.playing_all_songs = false;
+        self.detached_song = None;
+        self.play_order
+            .reset(self.current_playlist.len(), self.current_song);
+        self.play_song(song);
+        self.state.changed = true;
+        self.state.mode = InputMode::Main;
+    }
+
     fn handle_key(&mut self, key: KeyEvent) -> Result<bool> {
         let mut scripting = self.scripting.take().unwrap();
         let mode = self.input_mode();
@@ -599,12 +919,27 @@ impl RustPlay {
             self.state.last_mode = InputMode::SearchInput;
             match self.search_component.handle_key(key)? {
                 KeyReturn::Search(query) => {
-                    self.search(&query)?;
+                    if let Some(selected) = self.search_preview.selected() {
+                        let songs = self.search_preview.songs().to_vec();
+                        let song = songs[selected].clone();
+                        self.play_from_list(Rc::new(SongArray { songs }), &song);
+                    } else {
+                        self.search(&query)?;
+                    }
+                    self.search_preview.clear();
                 }
                 KeyReturn::ExitMenu => {
+                    self.search_preview.clear();
                     self.state.changed = true;
                     self.state.mode = InputMode::Main;
                 }
+                KeyReturn::Navigate => match key.code {
+                    KeyCode::Up => self.search_preview.handle_nav(MenuNav::Up),
+                    KeyCode::Down => self.search_preview.handle_nav(MenuNav::Down),
+                    KeyCode::PageUp => self.search_preview.handle_nav(MenuNav::PageUp),
+                    KeyCode::PageDown => self.search_preview.handle_nav(MenuNav::PageDown),
+                    _ => {}
+                },
                 _ => {}
             }
         }
@@ -613,9 +948,15 @@ impl RustPlay {
 
     pub fn update(&mut self) -> Result<()> {
         if self.state.done {
-            self.next_song();
+            self.song_ended();
             self.state.done = false;
         }
+        self.update_search_preview();
+        let changed_dirs = self.indexer.take_changed_dirs();
+        if changed_dirs.contains(&self.current_dir) {
+            self.update_dir_menu()?;
+            self.state.changed = true;
+        }
         let mut next_fft_at = None;
         while let Ok((meta, val)) = self.info_consumer.try_recv() {
             if meta != "fft" && meta != "fft_at" {
@@ -666,9 +1007,7 @@ impl RustPlay {
             self.state.update_meta(&meta, val);
         }
 
-        if let Some(Value::Number(len)) = self.state.meta.get("length") {
-            self.state.len_msec = (len * 1000.0) as usize;
-        }
+        self.state.len_msec = self.play_length_msec();
         if let Ok(cmd) = self.media_keys_receiver.try_recv() {
             match cmd {
                 MediaKeyEvent::Next => self.next_song(),
@@ -676,6 +1015,9 @@ impl RustPlay {
                 MediaKeyEvent::Play => self.play_pause(),
                 MediaKeyEvent::Pause => self.play_pause(),
                 MediaKeyEvent::PlayPause => self.play_pause(),
+                MediaKeyEvent::Seek(usec) => self.seek(usec as f64 / 1_000_000.0),
+                MediaKeyEvent::SetPosition(usec) => self.seek_to(usec as f64 / 1_000_000.0),
+                MediaKeyEvent::SetVolume(volume) => self.change_volume(|v| v.set(volume)),
                 _ => (),
             }
         }
@@ -684,7 +1026,7 @@ impl RustPlay {
 
     pub fn handle_events(&mut self) -> Result<bool> {
         if self.no_term {
-            return Ok(false);
+            return Ok(self.state.quit || QUIT_SIGNALED.load(Ordering::SeqCst));
         }
         if self.state.quit {
             return Ok(true);
@@ -706,14 +1048,19 @@ impl RustPlay {
         Ok(self.state.quit)
     }
 
-    fn get_song(&self, n: usize) -> Option<FileInfo> {
-        if n < self.current_playlist.len() {
-            return Some(self.current_playlist.get(n));
+    /// Start playing `song` and add it to the history
+    pub(crate) fn play_song(&mut self, song: &FileInfo) {
+        if song.path.as_os_str().is_empty() {
+            log!("No song to play");
+            return;
         }
-        None
+        self.finish_history();
+        self.history.add(song.path());
+        self.update_history_menu();
+        self.load_song(song);
     }
 
-    pub(crate) fn play_song(&mut self, song: &FileInfo) {
+    fn load_song(&mut self, song: &FileInfo) {
         self.state.clear_meta();
         for (name, val) in &song.meta_data {
             log!("INDEX-META {name} = {val}");
@@ -730,13 +1077,29 @@ impl RustPlay {
             let s = fname.to_string_lossy().to_string();
             self.state.update_meta("file_name", Value::Text(s));
         }
-        if let Some(next_song) = self.get_song(self.current_song + 1) {
-            self.state
-                .update_meta("next_song", Value::Text(next_song.full_song_name()));
-        }
-
+        self.state
+            .set_meta("play_mode", self.play_order.mode.label().to_owned());
+        self.state
+            .set_meta("volume", self.volume.percent().to_string());
+
+        self.play_time_msec = self
+            .settings
+            .play_time(song.path())
+            .map_or(0, |secs| (secs * 1000.0) as usize);
         let path = song.path().to_owned();
-        self.send_cmd(move |player| player.load(&path));
+        if let Some(msec) = self.pending_crossfade.take() {
+            self.send_cmd(move |player| player.crossfade(&path, msec));
+        } else {
+            self.send_cmd(move |player| player.load(&path));
+        }
+        // Subtune given in a playlist or favorite
+        if let Value::Number(subtune) = song.get("subtune") {
+            let subtune = *subtune as i32;
+            self.send_cmd(move |player| player.set_song(subtune));
+        }
+        self.use_known_length();
+        // After loading, so the preloaded song is used before it is replaced
+        self.update_next_song();
     }
 
     pub(crate) fn play_file(&mut self, file_name: String) {
@@ -746,57 +1109,433 @@ impl RustPlay {
         });
     }
 
+    /// Show the song that will be played after the current one, and have
+    /// the player load it in the background
+    fn update_next_song(&mut self) {
+        let next = if let Some(song) = self.queue.peek() {
+            Some(song.clone())
+        } else {
+            self.play_order
+                .peek_next(self.current_song, self.current_playlist.len())
+                .map(|n| self.current_playlist.get(n))
+        };
+        let name = next.as_ref().map(FileInfo::full_song_name);
+        self.state.set_meta("next_song", name.unwrap_or_default());
+        if let Some(song) = next {
+            let path = song.path().to_owned();
+            let format = match song.get("format") {
+                Value::Text(format) => format.clone(),
+                _ => String::new(),
+            };
+            self.send_cmd(move |player| player.preload(&path, &format));
+        }
+        self.state.changed = true;
+    }
+
     pub fn prev_song(&mut self) {
+        if let Some(entry) = self.history.back().cloned() {
+            self.play_history_entry(&entry);
+            return;
+        }
+        self.detached_song = None;
         if !self.current_playlist.is_empty() {
-            if self.current_song > 0 {
-                self.current_song -= 1;
+            if let Some(prev) = self
+                .play_order
+                .prev(self.current_song, self.current_playlist.len())
+            {
+                self.current_song = prev;
             }
-            let song = self.current_playlist.get(self.current_song);
+            let song = self.skip_missing(PlayOrder::prev);
             self.play_song(&song);
         }
     }
+
     pub fn next_song(&mut self) {
+        // After going back in history, go forward again before continuing
+        if self.history.walking()
+            && let Some(entry) = self.history.forward().cloned()
+        {
+            self.play_history_entry(&entry);
+            return;
+        }
+        if let Some(song) = self.queue.pop() {
+            self.update_queue_menu();
+            self.play_queued(song);
+            return;
+        }
+        self.detached_song = None;
+        if self.play_order.mode == PlayMode::ShuffleAll && !self.playing_all_songs {
+            self.current_playlist = self.indexer.get_all_songs();
+            self.playing_all_songs = true;
+            self.play_order.shuffle(self.current_playlist.len(), None);
+            self.current_song = self.play_order.current().unwrap_or(0);
+        } else if let Some(next) = self
+            .play_order
+            .next(self.current_song, self.current_playlist.len())
+        {
+            self.current_song = next;
+        }
         if !self.current_playlist.is_empty() {
-            if (self.current_song + 1) < self.current_playlist.len() {
-                self.current_song += 1;
+            let song = self.skip_missing(PlayOrder::next);
+            self.play_song(&song);
+        }
+    }
+
+    /// The current song, or the first song found by stepping with `step`
+    /// if it is missing. Songs removed from the index, or not committed yet,
+    /// leave empty entries.
+    fn skip_missing(
+        &mut self,
+        step: fn(&mut PlayOrder, usize, usize) -> Option<usize>,
+    ) -> FileInfo {
+        let len = self.current_playlist.len();
+        let mut song = self.current_playlist.get(self.current_song);
+        for _ in 0..len {
+            if !song.path.as_os_str().is_empty() {
+                break;
             }
-            let song = self.current_playlist.get(self.current_song);
+            let Some(n) = step(&mut self.play_order, self.current_song, len) else {
+                break;
+            };
+            self.current_song = n;
+            song = self.current_playlist.get(n);
+        }
+        song
+    }
+
+    /// End the song once its length has passed. It is faded out first, or
+    /// crossfaded into the next song, if set up to.
+    fn check_song_end(&mut self, play_time: usize) {
+        let len = self.state.len_msec;
+        // Subtunes are switched within the same player, and some players
+        // can only have one song loaded, so those are faded out instead
+        let crossfade = if self.next_is_subtune() || !can_crossfade(self.state.get_meta("format")) {
+            0
+        } else {
+            (self.settings.crossfade.max(0.0) * 1000.0) as usize
+        };
+        let fade = if crossfade > 0 {
+            crossfade
+        } else {
+            (self.settings.fade_out.max(0.0) * 1000.0) as usize
+        };
+        if len == 0 || play_time + fade < len {
+            // Also after seeking back, or when the next song has started
+            self.song_ending = false;
+            return;
+        }
+        if self.song_ending {
+            return;
+        }
+        self.song_ending = true;
+        let remaining = len.saturating_sub(play_time);
+        if remaining == 0 {
+            self.song_ended();
+        } else if crossfade > 0 {
+            self.pending_crossfade = Some(remaining);
+            self.song_ended();
+            self.pending_crossfade = None;
+        } else {
+            // The player says when it is done
+            self.send_cmd(move |player| player.fade_out(remaining));
+        }
+    }
+
+    /// The next subtune of the playing song will be played after this one
+    fn next_is_subtune(&self) -> bool {
+        self.settings.subtunes.play_all && self.state.song + 1 < self.state.songs
+    }
+
+    /// Called when the current song is done playing
+    fn song_ended(&mut self) {
+        if self.next_is_subtune() {
+            self.advance_subtune();
+        } else if self.play_order.mode == PlayMode::RepeatOne
+            && let Some(song) = self.playing_song()
+        {
             self.play_song(&song);
+        } else {
+            self.next_song();
         }
     }
 
-    /// Update rustplay, read any meta data from player etc
-    /// Add a path to the indexer
-    pub fn add_path(&mut self, song: &Path) -> Result<()> {
-        self.indexer.add_path(song)
+    /// Go on to the next subtune of the playing song
+    fn advance_subtune(&mut self) {
+        let next = self.state.song + 1;
+        // The player may not report the length of the new subtune
+        let length = self
+            .state
+            .get_meta("lengths")
+            .split(' ')
+            .nth(next as usize)
+            .and_then(|l| l.parse::<f64>().ok())
+            .unwrap_or(0.0);
+        self.state
+            .update_meta("song", Value::Number(f64::from(next)));
+        self.state.update_meta("length", Value::Number(length));
+        self.state.len_msec = self.play_length_msec();
+        self.msec.store(0, Ordering::SeqCst);
+        self.send_cmd(move |player| player.set_song(next + 1));
+        self.use_known_length();
     }
 
-    fn load_favorites(favorites_dir: &Path) -> Vec<FileInfo> {
-        let mut songs = vec![];
-        if let Ok(entries) = fs::read_dir(favorites_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                // TODO: HACK! we need to know which files are secondary for real
-                if path.is_file()
-                    && path
-                        .extension()
-                        .map(|e| e != "meta" && e != "smpl")
-                        .unwrap_or(true)
-                {
-                    log!("{path:?}");
-                    let file_info = SongIndexer::identify_song(&path);
-                    songs.push(file_info);
-                }
+    /// Songs with a length from the index, a playlist or a favorite end
+    /// then, even if the player does not know it, so they are not checked
+    /// for loops
+    fn use_known_length(&mut self) {
+        if let Some(Value::Number(len)) = self.state.meta.get("length")
+            && *len > 0.0
+        {
+            self.send_cmd(Player::disable_loop_detection);
+        }
+    }
+
+    /// How long to play the current song, or 0 to play it until it ends.
+    /// Songs with no known length are played for the time set for their
+    /// format. When playing all subtunes, they must end for the next one to
+    /// start, so they get a default time if their format has none.
+    fn play_length_msec(&self) -> usize {
+        match self.state.meta.get("length") {
+            Some(Value::Number(len)) if *len > 0.0 => (len * 1000.0) as usize,
+            _ if self.play_time_msec == 0
+                && self.settings.subtunes.play_all
+                && self.state.songs > 1 =>
+            {
+                (DEFAULT_SUBTUNE_TIMEOUT * 1000.0) as usize
             }
+            _ => self.play_time_msec,
         }
-        songs
     }
 
-    fn get_playing_song(&self) -> Option<FileInfo> {
-        if self.current_song >= self.current_playlist.len() {
-            return None;
+    /// The song currently playing, from the queue or the playlist
+    fn playing_song(&self) -> Option<FileInfo> {
+        if let Some(song) = &self.detached_song {
+            return Some(song.clone());
         }
-        let mut song = self.current_playlist.get(self.current_song);
+        (self.current_song < self.current_playlist.len())
+            .then(|| self.current_playlist.get(self.current_song))
+    }
+
+    /// Play a song from the history without adding it to the history again
+    fn play_history_entry(&mut self, entry: &HistoryEntry) {
+        self.finish_history();
+        let song = entry.to_file_info();
+        self.detached_song = Some(song.clone());
+        self.load_song(&song);
+        self.set_subtune(entry.subtune);
+    }
+
+    /// Switch to subtune `subtune` (0 = first) of the song just loaded
+    fn set_subtune(&mut self, subtune: i32) {
+        if subtune > 0 {
+            self.send_cmd(move |player| player.set_song(subtune + 1));
+        }
+    }
+
+    /// Save what we know about the song we are leaving to the history
+    fn finish_history(&mut self) {
+        let listened = self.msec.load(Ordering::Relaxed) as u64;
+        let title = match self.state.get_meta("title") {
+            "" => self.state.get_meta("game"),
+            title => title,
+        };
+        let composer = self.state.get_meta("composer");
+        self.history
+            .finish(listened, self.state.song, title, composer);
+    }
+
+    fn update_history_menu(&mut self) {
+        let play_counts = Rc::new(self.history.play_counts());
+        for menu in self.menus.values_mut() {
+            menu.set_play_counts(play_counts.clone());
+        }
+        let songs = self.history.songs();
+        self.get_menu(&MenuId::History)
+            .set_songs("History", Rc::new(SongArray { songs }));
+    }
+
+    pub fn show_history(&mut self) {
+        if !self.history.is_empty() {
+            self.current_menu = MenuId::History;
+            self.state.mode = InputMode::ResultScreen;
+        } else {
+            self.state.info("No history yet");
+        }
+    }
+
+    fn play_queued(&mut self, song: FileInfo) {
+        self.detached_song = Some(song.clone());
+        self.play_song(&song);
+        self.state.changed = true;
+    }
+
+    fn update_queue_menu(&mut self) {
+        let songs = self.queue.collection();
+        let count = self.queue.len();
+        let menu = self.get_menu(&MenuId::Queue);
+        let selected = menu.selected;
+        menu.set_songs("Queue", songs);
+        menu.select(selected);
+        menu.set_info(format!("{count} songs"));
+        self.update_next_song();
+    }
+
+    /// Add a song to the end of the play queue
+    fn enqueue(&mut self, song: FileInfo) {
+        if song.file_type == FileType::Dir {
+            return;
+        }
+        self.state.info(format!("Queued {}", song.full_song_name()));
+        self.queue.push(song);
+        self.update_queue_menu();
+    }
+
+    /// Add a song first in the play queue
+    fn enqueue_next(&mut self, song: FileInfo) {
+        if song.file_type == FileType::Dir {
+            return;
+        }
+        self.state
+            .info(format!("Playing next: {}", song.full_song_name()));
+        self.queue.push_next(song);
+        self.update_queue_menu();
+    }
+
+    /// Remove the selected song from the queue, when the queue is shown
+    fn dequeue_selected(&mut self) {
+        if self.current_menu != MenuId::Queue {
+            return;
+        }
+        let selected = self.current_menu().selected_source();
+        if self.queue.remove(selected).is_some() {
+            self.update_queue_menu();
+        }
+        if self.queue.is_empty() {
+            self.show_main();
+        }
+    }
+
+    /// Move the selected song in the queue `delta` steps down (or up if negative)
+    fn move_queued(&mut self, delta: i32) {
+        if self.current_menu != MenuId::Queue {
+            return;
+        }
+        let selected = self.current_menu().selected_source();
+        if let Some(pos) = self.queue.move_song(selected, delta) {
+            self.update_queue_menu();
+            self.get_menu(&MenuId::Queue).select_source(pos);
+        }
+    }
+
+    fn clear_queue(&mut self) {
+        self.queue.clear();
+        self.update_queue_menu();
+        if self.current_menu == MenuId::Queue && self.state.mode == InputMode::ResultScreen {
+            self.show_main();
+        }
+    }
+
+    fn get_queue(&self) -> Vec<FileInfo> {
+        self.queue.songs()
+    }
+
+    /// Save the current playlist, the search result or the favorites
+    /// (`what` is "playlist", "search" or "favorites") as M3U
+    fn export_m3u(&mut self, file: &str, what: &str) -> Result<()> {
+        let songs = match what {
+            "playlist" => self.current_playlist.clone(),
+            "search" => self.menus[&MenuId::Search].songs().clone(),
+            "favorites" => self.menus[&MenuId::Favorites].songs().clone(),
+            _ => bail!("Unknown song list '{what}'"),
+        };
+        let songs = songs.get_range(0, songs.len());
+        playlist::save_m3u(Path::new(file), &songs)?;
+        self.state
+            .info(format!("Saved {} songs to {file}", songs.len()));
+        Ok(())
+    }
+
+    pub fn show_queue(&mut self) {
+        if !self.queue.is_empty() {
+            self.current_menu = MenuId::Queue;
+            self.state.mode = InputMode::ResultScreen;
+        } else {
+            self.state.info("Queue is empty");
+        }
+    }
+
+    fn set_play_mode(&mut self, mode: PlayMode) {
+        self.play_order.mode = mode;
+        self.play_order
+            .reset(self.current_playlist.len(), self.current_song);
+        self.state.set_meta("play_mode", mode.label().to_owned());
+        self.update_next_song();
+    }
+
+    fn play_mode(&self) -> PlayMode {
+        self.play_order.mode
+    }
+
+    /// Change the volume with `change`, and pass it on to the player
+    fn change_volume(&mut self, change: impl FnOnce(&mut Volume) -> Result<()>) {
+        if let Err(e) = change(&mut self.volume) {
+            log!("Could not save volume: {e}");
+        }
+        let gain = self.volume.gain();
+        self.send_cmd(move |player| player.set_volume(gain));
+        let _ = self.media_sender.send(MediaKeyInfo::Volume(gain));
+        self.state
+            .set_meta("volume", self.volume.percent().to_string());
+        self.state.changed = true;
+    }
+
+    fn volume_up(&mut self) {
+        self.change_volume(Volume::up);
+    }
+
+    fn volume_down(&mut self) {
+        self.change_volume(Volume::down);
+    }
+
+    fn mute(&mut self) {
+        self.change_volume(Volume::toggle_mute);
+    }
+
+    fn cycle_play_mode(&mut self) {
+        self.set_play_mode(self.play_order.mode.next());
+        let name = self.play_order.mode.label();
+        self.state
+            .info(if name.is_empty() { "NORMAL" } else { name });
+    }
+
+    /// Sort the current menu. The order is kept for that menu.
+    fn set_sort_order(&mut self, sort: SortOrder) {
+        let play_counts = Rc::new(self.history.play_counts());
+        let menu = self.current_menu();
+        menu.set_play_counts(play_counts);
+        menu.set_sort(sort);
+        self.state.changed = true;
+    }
+
+    fn sort_order(&self) -> SortOrder {
+        self.menus[&self.current_menu].sort()
+    }
+
+    fn cycle_sort_order(&mut self) {
+        self.set_sort_order(self.sort_order().next());
+        let label = self.sort_order().label();
+        self.state
+            .info(if label.is_empty() { "UNSORTED" } else { label });
+    }
+
+    /// Update rustplay, read any meta data from player etc
+    /// Add a path to the indexer
+    pub fn add_path(&mut self, song: &Path) -> Result<()> {
+        self.indexer.add_path(song)
+    }
+
+    fn get_playing_song(&self) -> Option<FileInfo> {
+        let mut song = self.playing_song()?;
         let skip_tags: HashSet<&str> = [
             // "message",
             "startSong",
@@ -813,6 +1552,13 @@ impl RustPlay {
             }
             song.meta_data.insert(key.clone(), value.clone());
         }
+        // The subtune playing now, so a favorite starts where we are
+        if self.state.songs > 1 {
+            song.meta_data.insert(
+                "subtune".into(),
+                Value::Number(f64::from(self.state.song + 1)),
+            );
+        }
         Some(song)
     }
 
@@ -830,11 +1576,9 @@ impl RustPlay {
     }
 
     fn add_playing_to_favorites(&mut self) {
-        if self.current_song >= self.current_playlist.len() {
-            return;
+        if let Some(song) = self.get_playing_song() {
+            self.add_favorite(song);
         }
-        let song = self.current_playlist.get(self.current_song);
-        self.add_favorite(song);
     }
 
     fn add_char(&mut self, ke: KeyEvent) -> Result<()> {
@@ -853,62 +1597,15 @@ impl RustPlay {
         Ok(())
     }
 
+    /// Add `song` to the open playlist
     fn add_favorite(&mut self, song: FileInfo) {
-        let src = song.path();
-        let Some(file_name) = src.file_name() else {
-            return;
-        };
-        if let Err(e) = fs::create_dir_all(&self.favorites_dir) {
-            self.state
-                .messages
-                .push_back(Msg::Err(format!("Can't create favorites dir: {e}")));
-            return;
-        }
-
-        log!("FILES: {:?}", self.state.song_files);
-        let res = self.state.song_files.iter().try_for_each(|src| {
-            let dest = self.favorites_dir.join(src.file_name().unwrap_or_default());
-            fs::copy(src, &dest).map(|_| ())
-        });
-
-        let dest = self.favorites_dir.join(file_name);
-        match res {
-            Ok(_) => {
-                self.state.info("Added to favorites");
-                let meta_path = dest.with_extension(format!(
-                    "{}.meta",
-                    dest.extension()
-                        .map(|e| e.to_string_lossy())
-                        .unwrap_or_default()
-                ));
-                let mut table = toml::map::Map::new();
-                for (key, value) in &song.meta_data {
-                    match value {
-                        Value::Text(s) if !s.is_empty() => {
-                            table.insert(key.clone(), toml::Value::String(s.clone()));
-                        }
-                        Value::Number(n) => {
-                            let int = *n as i64;
-                            if *n == int as f64 {
-                                table.insert(key.clone(), toml::Value::Integer(int));
-                            } else {
-                                table.insert(key.clone(), toml::Value::Float(*n));
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-                if !table.is_empty()
-                    && let Ok(toml_str) = toml::to_string(&table)
-                {
-                    let _ = fs::write(&meta_path, &toml_str);
-                }
-            }
-            Err(e) => self.state.error(format!("Failed to copy: {e}")),
+        let name = self.open_playlist.clone();
+        match self.playlists.add(&name, &song) {
+            Ok(true) => self.state.info(format!("Added to {name}")),
+            Ok(false) => self.state.info(format!("Already in {name}")),
+            Err(e) => self.state.error(format!("Could not add to {name}: {e}")),
         }
-        let songs = Self::load_favorites(&self.favorites_dir);
-        self.get_menu(&MenuId::Favorites)
-            .set_songs("Favorites", Rc::new(SongArray { songs }));
+        self.update_playlist_menu();
     }
 
     /// Quit rustplay.
@@ -917,6 +1614,7 @@ impl RustPlay {
     ///
     /// Will panic if the player thread could not be joined.
     pub fn destroy(&mut self) -> Result<()> {
+        self.finish_history();
         if !self.no_term {
             RustPlay::restore_term()?;
         }
@@ -932,6 +1630,7 @@ impl RustPlay {
                 Err(err) => panic::resume_unwind(err),
             }
         }
+        archive::clear_cache();
         Ok(())
     }
 }
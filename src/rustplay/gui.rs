@@ -1,4 +1,5 @@
 use super::song::{FileInfo, SongArray, SongCollection};
+use super::sort_order::SortOrder;
 use crate::{log, term_extra::SetReverse};
 use anyhow::Result;
 use crossterm::{
@@ -9,9 +10,11 @@ use crossterm::{
     terminal::{Clear, ClearType},
 };
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 use std::{
     collections::HashMap,
     io::{Write, stdout},
+    path::PathBuf,
 };
 
 // SONG MENU
@@ -44,7 +47,14 @@ pub struct SongMenu {
     moved: bool,
     pub location: String,
     pub info: String,
+    /// The songs as shown, in `sort` order
     songs: Rc<dyn SongCollection>,
+    /// The songs in the order they were given
+    source: Rc<dyn SongCollection>,
+    /// Index in `source` for each shown song, empty when not sorted
+    order: Vec<usize>,
+    sort: SortOrder,
+    play_counts: Rc<HashMap<PathBuf, usize>>,
     stack: HashMap<String, FileInfo>,
 }
 
@@ -62,6 +72,10 @@ impl Default for SongMenu {
             location: String::new(),
             info: String::new(),
             songs: Rc::new(SongArray::default()),
+            source: Rc::new(SongArray::default()),
+            order: Vec::new(),
+            sort: SortOrder::Default,
+            play_counts: Rc::new(HashMap::new()),
             stack: HashMap::new(),
         }
     }
@@ -81,7 +95,8 @@ impl SongMenu {
         self.selected = 0;
         self.start_pos = 0;
         self.location = location.into();
-        self.songs = songs;
+        self.source = songs;
+        self.apply_sort();
         self.scrolled = true;
         if let Some(file_info) = self.stack.get(&self.location)
             && let Some(index) = self.songs.index_of(file_info)
@@ -96,6 +111,66 @@ impl SongMenu {
         &self.songs
     }
 
+    pub fn sort(&self) -> SortOrder {
+        self.sort
+    }
+
+    /// Show the songs in `sort` order, keeping the selected song
+    pub fn set_sort(&mut self, sort: SortOrder) {
+        let current = (self.selected < self.songs.len()).then(|| self.get_current());
+        self.sort = sort;
+        self.apply_sort();
+        self.selected = current
+            .and_then(|song| self.songs.index_of(&song))
+            .unwrap_or(0);
+        self.start_pos = 0;
+        self.scrolled = true;
+        self.update_scrolling();
+    }
+
+    /// Set how many times each song was played, for sorting on play count
+    pub fn set_play_counts(&mut self, play_counts: Rc<HashMap<PathBuf, usize>>) {
+        self.play_counts = play_counts;
+        if self.sort == SortOrder::PlayCount {
+            self.set_sort(self.sort);
+        }
+    }
+
+    fn apply_sort(&mut self) {
+        if self.sort == SortOrder::Default {
+            self.order.clear();
+            self.songs = self.source.clone();
+            return;
+        }
+        let songs = self.source.get_range(0, self.source.len());
+        self.order = self.sort.sort(&songs, &self.play_counts);
+        let songs = self.order.iter().map(|i| songs[*i].clone()).collect();
+        self.songs = Rc::new(SongArray { songs });
+    }
+
+    /// Index of the selected song in the songs as they were given
+    pub fn selected_source(&self) -> usize {
+        self.order
+            .get(self.selected)
+            .copied()
+            .unwrap_or(self.selected)
+    }
+
+    /// Move the cursor to the song at `index` in the songs as they were given
+    pub fn select_source(&mut self, index: usize) {
+        let index = self.order.iter().position(|i| *i == index).unwrap_or(index);
+        self.select(index);
+    }
+
+    /// Menu header text to the right of the location
+    fn header_info(&self) -> String {
+        match (self.sort.label(), self.info.as_str()) {
+            ("", info) => info.to_owned(),
+            (label, "") => format!("[{label}]"),
+            (label, info) => format!("[{label}] {info}"),
+        }
+    }
+
     fn fade(&self, i: usize) -> Color {
         let x: u8 = (155 + self.fader[i] * 10) as u8;
         Color::Rgb { r: x, g: x, b: x }
@@ -117,6 +192,7 @@ impl SongMenu {
         if self.scrolled {
             out.queue(Clear(ClearType::All))?;
         }
+        let info = self.header_info();
         if self.use_color {
             out.queue(SetForegroundColor(white))?
                 .queue(SetBackgroundColor(Color::DarkRed))?
@@ -124,7 +200,7 @@ impl SongMenu {
                 .queue(Print(format!(
                     "{}{:>width$}",
                     &self.location,
-                    &self.info,
+                    info,
                     width = self.width - self.location.len()
                 )))?
                 .queue(cursor::MoveTo(0, 1))?
@@ -134,7 +210,7 @@ impl SongMenu {
                 .queue(Print(format!(
                     "{}{:>width$}",
                     &self.location,
-                    &self.info,
+                    info,
                     width = self.width - self.location.len()
                 )))?
                 .queue(cursor::MoveTo(0, 1))?;
@@ -195,6 +271,13 @@ impl SongMenu {
         self.scrolled = true;
     }
 
+    /// Move the cursor to song number `index`
+    pub fn select(&mut self, index: usize) {
+        self.selected = index;
+        self.moved = true;
+        self.update_scrolling();
+    }
+
     pub fn get_current(&self) -> FileInfo {
         self.songs.get(self.selected).clone()
     }
@@ -340,6 +423,11 @@ impl SearchField {
 }
 
 impl SearchField {
+    /// The text typed so far
+    pub fn text(&self) -> String {
+        self.shell.command()
+    }
+
     pub fn draw(&self) -> Result<()> {
         let mut out = stdout();
 
@@ -397,6 +485,131 @@ impl SearchField {
     }
 }
 
+// Live search results
+
+/// Search results shown under the search field while typing
+#[derive(Default)]
+pub struct SearchPreview {
+    pub xpos: u16,
+    pub ypos: u16,
+    pub width: usize,
+    pub height: usize,
+    pub use_color: bool,
+    songs: Vec<FileInfo>,
+    /// Not set until the user moves into the results
+    selected: Option<usize>,
+    start_pos: usize,
+    /// The search text, and when it last changed
+    query: String,
+    edited: Option<Instant>,
+}
+
+impl SearchPreview {
+    pub fn new(xpos: u16, ypos: u16, use_color: bool) -> SearchPreview {
+        SearchPreview {
+            xpos,
+            ypos,
+            use_color,
+            ..SearchPreview::default()
+        }
+    }
+
+    /// Note the current search text. Returns the text to search for once it
+    /// has stayed the same for `delay`.
+    pub fn pending_query(&mut self, text: &str, delay: Duration) -> Option<String> {
+        if text != self.query {
+            self.query = text.to_owned();
+            self.edited = Some(Instant::now());
+            return None;
+        }
+        if self.edited?.elapsed() < delay {
+            return None;
+        }
+        self.edited = None;
+        Some(self.query.clone())
+    }
+
+    pub fn set_songs(&mut self, songs: Vec<FileInfo>) {
+        self.songs = songs;
+        self.selected = None;
+        self.start_pos = 0;
+    }
+
+    pub fn clear(&mut self) {
+        self.set_songs(Vec::new());
+        self.query.clear();
+        self.edited = None;
+    }
+
+    pub fn songs(&self) -> &[FileInfo] {
+        &self.songs
+    }
+
+    /// Index of the song the user has moved to, if any
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    pub fn handle_nav(&mut self, nav: MenuNav) {
+        if self.songs.is_empty() {
+            return;
+        }
+        let last = self.songs.len() - 1;
+        let page = self.height.max(1);
+        self.selected = match (self.selected, nav) {
+            (None, MenuNav::Up | MenuNav::PageUp) => None,
+            (None, MenuNav::Down | MenuNav::PageDown) => Some(0),
+            (Some(0), MenuNav::Up) => None,
+            (Some(s), MenuNav::Up) => Some(s - 1),
+            (Some(s), MenuNav::PageUp) => Some(s.saturating_sub(page)),
+            (Some(s), MenuNav::Down) => Some((s + 1).min(last)),
+            (Some(s), MenuNav::PageDown) => Some((s + page).min(last)),
+        };
+        if let Some(selected) = self.selected {
+            if selected < self.start_pos {
+                self.start_pos = selected;
+            } else if selected >= self.start_pos + page {
+                self.start_pos = selected + 1 - page;
+            }
+        }
+    }
+
+    pub fn draw(&self) -> Result<()> {
+        let mut out = stdout();
+        let end = (self.start_pos + self.height).min(self.songs.len());
+        let visible = self.songs.get(self.start_pos..end).unwrap_or_default();
+        for row in 0..self.height {
+            out.queue(cursor::MoveTo(self.xpos, self.ypos + row as u16))?
+                .queue(Clear(ClearType::UntilNewLine))?;
+            let Some(song) = visible.get(row) else {
+                continue;
+            };
+            let name: String = song
+                .full_song_name()
+                .chars()
+                .take(self.width.saturating_sub(3))
+                .collect();
+            let is_selected = self.selected == Some(self.start_pos + row);
+            if self.use_color {
+                let color = if is_selected {
+                    Color::White
+                } else {
+                    Color::DarkGrey
+                };
+                out.queue(SetForegroundColor(color))?;
+            } else {
+                out.queue(SetReverse(is_selected))?;
+            }
+            out.queue(Print(if is_selected { "> " } else { "  " }))?
+                .queue(Print(name))?;
+            if !self.use_color {
+                out.queue(SetReverse(false))?;
+            }
+        }
+        Ok(())
+    }
+}
+
 // Create a target_count colors from source, by evenly distributing the source
 // colors in the new array an then interpolating the values in between.
 pub fn interpolate_colors(source: &[u32], target_count: usize) -> Vec<u32> {
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::song::FileInfo;
+use crate::log;
+use crate::value::Value;
+
+/// Max number of songs kept in the history file
+const MAX_HISTORY: usize = 1000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct HistoryEntry {
+    pub path: PathBuf,
+    /// Subtune index, as reported by the player (0 = first)
+    pub subtune: i32,
+    /// When the song started playing, in seconds since the epoch
+    pub timestamp: u64,
+    /// How far into the song we got before switching away from it
+    pub listened_msec: u64,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub composer: String,
+}
+
+impl HistoryEntry {
+    pub fn to_file_info(&self) -> FileInfo {
+        let mut song = FileInfo {
+            path: self.path.clone(),
+            ..FileInfo::default()
+        };
+        if !self.title.is_empty() {
+            song.meta_data
+                .insert("title".into(), Value::Text(self.title.clone()));
+        }
+        if !self.composer.is_empty() {
+            song.meta_data
+                .insert("composer".into(), Value::Text(self.composer.clone()));
+        }
+        song
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct HistoryFile {
+    entries: Vec<HistoryEntry>,
+}
+
+/// All songs played, oldest first, with a cursor for stepping back and
+/// forth through them.
+#[derive(Default)]
+pub struct History {
+    entries: Vec<HistoryEntry>,
+    file: Option<PathBuf>,
+    /// The entry playing while walking the history with `back()` and `forward()`
+    cursor: Option<usize>,
+    /// The last entry is still playing and has not been saved
+    open: bool,
+}
+
+impl History {
+    /// Load history from `file`. Starts out empty if the file is missing
+    /// or can not be read.
+    pub fn load(file: PathBuf) -> Self {
+        let entries = fs::read_to_string(&file)
+            .ok()
+            .and_then(|text| toml::from_str::<HistoryFile>(&text).ok())
+            .map(|h| h.entries)
+            .unwrap_or_default();
+        Self {
+            entries,
+            file: Some(file),
+            ..Self::default()
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let history = HistoryFile {
+            entries: self.entries.clone(),
+        };
+        fs::write(file, toml::to_string(&history)?)?;
+        Ok(())
+    }
+
+    /// Record that `path` started playing. Ends walking the history.
+    pub fn add(&mut self, path: &Path) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self.entries.push(HistoryEntry {
+            path: path.to_owned(),
+            timestamp,
+            ..HistoryEntry::default()
+        });
+        if self.entries.len() > MAX_HISTORY {
+            self.entries.drain(..self.entries.len() - MAX_HISTORY);
+        }
+        self.cursor = None;
+        self.open = true;
+    }
+
+    /// Fill in what we know about the playing song when we leave it,
+    /// and write the history to disk.
+    pub fn finish(&mut self, listened_msec: u64, subtune: i32, title: &str, composer: &str) {
+        if !self.open {
+            return;
+        }
+        self.open = false;
+        if let Some(entry) = self.entries.last_mut() {
+            entry.listened_msec = listened_msec;
+            entry.subtune = subtune;
+            entry.title = title.to_owned();
+            entry.composer = composer.to_owned();
+        }
+        if let Err(e) = self.save() {
+            log!("Could not save history: {e}");
+        }
+    }
+
+    /// Step back to the song played before the current position
+    pub fn back(&mut self) -> Option<&HistoryEntry> {
+        let current = self.cursor.or(self.entries.len().checked_sub(1))?;
+        let pos = current.checked_sub(1)?;
+        self.cursor = Some(pos);
+        self.entries.get(pos)
+    }
+
+    /// Step forward again after `back()`. Returns `None` when we are back
+    /// at the most recent song.
+    pub fn forward(&mut self) -> Option<&HistoryEntry> {
+        let pos = self.cursor? + 1;
+        self.cursor = (pos + 1 < self.entries.len()).then_some(pos);
+        self.entries.get(pos)
+    }
+
+    /// True if we have stepped back in the history
+    pub fn walking(&self) -> bool {
+        self.cursor.is_some()
+    }
+
+    /// Entry number `index`, counting from the most recent
+    pub fn get_recent(&self, index: usize) -> Option<&HistoryEntry> {
+        self.entries.iter().rev().nth(index)
+    }
+
+    /// All songs, most recent first
+    pub fn songs(&self) -> Vec<FileInfo> {
+        self.entries
+            .iter()
+            .rev()
+            .map(HistoryEntry::to_file_info)
+            .collect()
+    }
+
+    /// How many times each song in the history was played
+    pub fn play_counts(&self) -> HashMap<PathBuf, usize> {
+        let mut counts = HashMap::new();
+        for entry in &self.entries {
+            *counts.entry(entry.path.clone()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::History;
+
+    #[test]
+    fn walk_back_and_forward() {
+        let mut history = History::default();
+        for name in ["a", "b", "c"] {
+            history.add(Path::new(name));
+        }
+        assert_eq!(history.back().unwrap().path, PathBuf::from("b"));
+        assert_eq!(history.back().unwrap().path, PathBuf::from("a"));
+        assert!(history.back().is_none());
+        assert_eq!(history.forward().unwrap().path, PathBuf::from("b"));
+        assert!(history.walking());
+        assert_eq!(history.forward().unwrap().path, PathBuf::from("c"));
+        assert!(!history.walking());
+        assert!(history.forward().is_none());
+        history.add(Path::new("a"));
+        assert_eq!(history.play_counts()[Path::new("a")], 2);
+    }
+
+    #[test]
+    fn history_is_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("history.toml");
+        let mut history = History::load(file.clone());
+        history.add(Path::new("music.mod"));
+        history.finish(12000, 2, "Title", "Composer");
+
+        let loaded = History::load(file);
+        let entry = loaded.get_recent(0).unwrap();
+        assert_eq!(entry.path, PathBuf::from("music.mod"));
+        assert_eq!(entry.listened_msec, 12000);
+        assert_eq!(entry.subtune, 2);
+        assert_eq!(loaded.songs()[0].get_title(), "Title");
+    }
+}
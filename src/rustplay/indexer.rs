@@ -1,12 +1,13 @@
 use std::collections::hash_map::DefaultHasher;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::hash::{Hash, Hasher};
 use std::io::Read;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::mpsc::Receiver;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
 use std::sync::{Arc, LazyLock, Mutex, MutexGuard, mpsc};
 use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant, UNIX_EPOCH};
@@ -15,27 +16,38 @@ use serde::{Deserialize, Serialize};
 
 use id3::{Tag, TagLike};
 use itertools::Itertools;
+use lru::LruCache;
 use musix::SongInfo;
+use regex::Regex;
 use std::ops::Bound;
 use tantivy::Term;
 use tantivy::collector::TopDocs;
-use tantivy::query::{QueryParser, RangeQuery, TermQuery};
-use tantivy::schema::IndexRecordOption;
+use tantivy::query::{
+    BooleanQuery, FuzzyTermQuery, Occur, Query, QueryParser, RangeQuery, RegexQuery, TermQuery,
+};
+use tantivy::schema::{IndexRecordOption, TextFieldIndexing, TextOptions};
+use tantivy::tokenizer::{
+    AsciiFoldingFilter, LowerCaser, RemoveLongFilter, SimpleTokenizer, TextAnalyzer,
+};
 
 use anyhow::Context;
 use anyhow::Result;
 use anyhow::anyhow;
+use tantivy::directory::MmapDirectory;
 use tantivy::{Index, IndexWriter, ReloadPolicy, doc};
 use tantivy::{
     IndexReader,
-    schema::{Field, OwnedValue, STORED, STRING, Schema, TEXT, TantivyDocument},
+    schema::{FAST, Field, INDEXED, OwnedValue, STORED, STRING, Schema, TEXT, TantivyDocument},
 };
 use walkdir::WalkDir;
 
+use crate::archive;
 use crate::log;
 use crate::value::Value;
 
 use super::song::{FileInfo, FileType, SongCollection};
+use super::song_lengths::sid_lengths;
+use super::watcher::{Change, DirWatcher};
 
 #[inline]
 /// Convert ISO-8859-1 slice to utf8 String (For text in SID header)
@@ -47,37 +59,82 @@ fn slice_to_string(slice: &[u8]) -> String {
         .collect()
 }
 
-const INITIAL_SONG_COUNT: usize = 100;
+const PAGE_SIZE: usize = 100;
+/// Number of pages of songs kept in memory
+const PAGE_CACHE_SIZE: usize = 50;
+
+/// Bump when the schema changes, so old indexes on disk are not used
+const INDEX_VERSION: u32 = 6;
 
 static MODLAND_FORMATS: LazyLock<HashSet<&'static str>> =
     LazyLock::new(|| include_str!("modland_formats.txt").lines().collect());
 
+#[allow(clippy::unwrap_used)]
+static YEAR_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b(19[4-9]\d|20\d\d)\b").unwrap());
+
+/// Find a year in a copyright string like "1987 Thalamus"
+fn year_from_text(text: &str) -> Option<u32> {
+    YEAR_RE.captures(text)?.get(1)?.as_str().parse().ok()
+}
+
 // --- Directory cache types and utilities ---
 
+/// The parts of a `Value` that can be stored in the cache and the index
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+enum CachedValue {
+    Text(String),
+    Number(f64),
+}
+
+type CachedMeta = HashMap<String, CachedValue>;
+
+fn meta_to_cache(meta: &HashMap<String, Value>) -> CachedMeta {
+    meta.iter()
+        .filter_map(|(key, value)| match value {
+            Value::Text(s) => Some((key.clone(), CachedValue::Text(s.clone()))),
+            Value::Number(n) => Some((key.clone(), CachedValue::Number(*n))),
+            _ => None,
+        })
+        .collect()
+}
+
+fn meta_from_cache(meta: CachedMeta) -> HashMap<String, Value> {
+    meta.into_iter()
+        .map(|(key, value)| match value {
+            CachedValue::Text(s) => (key, Value::Text(s)),
+            CachedValue::Number(n) => (key, Value::Number(n)),
+        })
+        .collect()
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 struct CachedFileInfo {
     path: PathBuf,
-    title: String,
-    composer: String,
+    meta_data: CachedMeta,
 }
 
 impl CachedFileInfo {
     fn from_file_info(fi: &FileInfo) -> Self {
         CachedFileInfo {
             path: fi.path.clone(),
-            title: fi.get_title().to_owned(),
-            composer: fi.get("composer").to_string(),
+            meta_data: meta_to_cache(&fi.meta_data),
         }
     }
 
     fn into_file_info(self) -> FileInfo {
         FileInfo {
             path: self.path,
+            meta_data: meta_from_cache(self.meta_data),
             ..Default::default()
         }
     }
 }
 
+/// Written first in every cache file. Bump the version when `DirCache`
+/// changes so old cache files are ignored.
+const CACHE_MAGIC: &[u8; 4] = b"OPDC";
+const CACHE_VERSION: u32 = 4;
+
 #[derive(Serialize, Deserialize)]
 struct DirCache {
     mtime_secs: u64,
@@ -91,6 +148,26 @@ fn cache_base_dir() -> Option<PathBuf> {
     Some(base)
 }
 
+/// Where to keep the index for a given set of root paths
+fn index_dir_for_roots(base: &Path, roots: &[PathBuf]) -> PathBuf {
+    // Paths are stored as given, so relative roots must give a different
+    // index when run from another directory
+    let mut roots = roots
+        .iter()
+        .map(|r| {
+            (
+                r.to_owned(),
+                r.canonicalize().unwrap_or_else(|_| r.to_owned()),
+            )
+        })
+        .collect_vec();
+    roots.sort();
+    roots.dedup();
+    let mut hasher = DefaultHasher::new();
+    roots.hash(&mut hasher);
+    base.join(format!("index-v{INDEX_VERSION}-{:016x}", hasher.finish()))
+}
+
 fn cache_file_for_dir(base: &Path, dir: &Path) -> PathBuf {
     let abs = dir.canonicalize().unwrap_or_else(|_| dir.to_owned());
     let mut hasher = DefaultHasher::new();
@@ -105,10 +182,20 @@ fn dir_mtime(dir: &Path) -> Option<(u64, u32)> {
     Some((dur.as_secs(), dur.subsec_nanos()))
 }
 
+/// Directory mtime as nanoseconds, for storing in the index
+fn dir_mtime_nanos(dir: &Path) -> Option<u64> {
+    dir_mtime(dir).map(|(secs, nanos)| secs * 1_000_000_000 + u64::from(nanos))
+}
+
 fn load_cache(base: &Path, dir: &Path) -> Option<DirCache> {
     let path = cache_file_for_dir(base, dir);
     let data = std::fs::read(path).ok()?;
-    let cache: DirCache = bincode::deserialize(&data).ok()?;
+    let data = data.strip_prefix(CACHE_MAGIC)?;
+    let (version, data) = data.split_first_chunk::<4>()?;
+    if u32::from_le_bytes(*version) != CACHE_VERSION {
+        return None;
+    }
+    let cache: DirCache = bincode::deserialize(data).ok()?;
     let (secs, nanos) = dir_mtime(dir)?;
     if cache.mtime_secs == secs && cache.mtime_nanos == nanos {
         Some(cache)
@@ -120,7 +207,10 @@ fn load_cache(base: &Path, dir: &Path) -> Option<DirCache> {
 fn save_cache(base: &Path, dir: &Path, cache: &DirCache) {
     let path = cache_file_for_dir(base, dir);
     if let Ok(data) = bincode::serialize(cache) {
-        let _ = std::fs::write(path, data);
+        let mut contents = CACHE_MAGIC.to_vec();
+        contents.extend_from_slice(&CACHE_VERSION.to_le_bytes());
+        contents.extend_from_slice(&data);
+        let _ = std::fs::write(path, contents);
     }
 }
 
@@ -134,12 +224,24 @@ pub struct SongIndexer {
     reader: IndexReader,
     title_field: Field,
     composer_field: Field,
+    game_field: Field,
+    format_field: Field,
+    copyright_field: Field,
+    year_field: Field,
+    length_field: Field,
+    ext_field: Field,
     path_field: Field,
+    path_text_field: Field,
     parent_field: Field,
     index_field: Field,
+    mtime_field: Field,
+    meta_field: Field,
 
-    initial_songs: VecDeque<FileInfo>,
+    /// Songs in index order, `PAGE_SIZE` per page
+    pages: LruCache<usize, Vec<FileInfo>>,
     count: AtomicUsize,
+    /// Search with typo tolerance by default
+    fuzzy: bool,
 }
 
 fn get_value(doc: &TantivyDocument, field: Field) -> Option<Value> {
@@ -153,6 +255,13 @@ fn get_value(doc: &TantivyDocument, field: Field) -> Option<Value> {
     None
 }
 
+fn get_u64(doc: &TantivyDocument, field: Field) -> Option<u64> {
+    match OwnedValue::from(doc.get_first(field)?) {
+        OwnedValue::U64(n) => Some(n),
+        _ => None,
+    }
+}
+
 fn get_string(doc: &TantivyDocument, field: Field) -> Result<String> {
     if let Some(path_val) = doc.get_first(field) {
         let owned: OwnedValue = path_val.into();
@@ -164,17 +273,112 @@ fn get_string(doc: &TantivyDocument, field: Field) -> Result<String> {
     Ok(String::new())
 }
 
+/// Tokenizer for names, so that "Hülsbeck" can be found as "hulsbeck"
+const FOLDING_TOKENIZER: &str = "folding";
+
+fn folding_analyzer() -> TextAnalyzer {
+    TextAnalyzer::builder(SimpleTokenizer::default())
+        .filter(RemoveLongFilter::limit(40))
+        .filter(LowerCaser)
+        .filter(AsciiFoldingFilter)
+        .build()
+}
+
+/// Split `text` into words the way names are indexed
+fn fold_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    folding_analyzer()
+        .token_stream(text)
+        .process(&mut |token| words.push(token.text.clone()));
+    words
+}
+
+/// How many letters a word may be off in a fuzzy search
+fn fuzzy_distance(word: &str) -> u8 {
+    match word.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+fn song_schema() -> Schema {
+    let folding = TextOptions::default().set_indexing_options(
+        TextFieldIndexing::default()
+            .set_tokenizer(FOLDING_TOKENIZER)
+            .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+    );
+    let mut schema_builder = Schema::builder();
+    schema_builder.add_text_field("title", folding.clone() | STORED);
+    schema_builder.add_text_field("composer", folding.clone() | STORED);
+    schema_builder.add_text_field("game", folding);
+    schema_builder.add_text_field("format", TEXT);
+    schema_builder.add_text_field("copyright", TEXT);
+    schema_builder.add_u64_field("year", INDEXED);
+    schema_builder.add_u64_field("length", INDEXED);
+    schema_builder.add_text_field("ext", TEXT);
+    // The full path for looking up documents, and tokenized for searching
+    schema_builder.add_text_field("file", STRING | STORED);
+    schema_builder.add_text_field("path", TEXT);
+    schema_builder.add_text_field("parent", STRING | STORED);
+    schema_builder.add_u64_field("index", INDEXED | STORED | FAST);
+    // Only set for directories
+    schema_builder.add_u64_field("mtime", STORED);
+    // All meta data of a song, as a bincode `CachedMeta`
+    schema_builder.add_bytes_field("meta", STORED);
+    schema_builder.build()
+}
+
 impl SongIndexer {
+    /// Create an index that only lives in memory
     pub fn new() -> Result<Self> {
-        let mut schema_builder = Schema::builder();
-        let title_field = schema_builder.add_text_field("title", TEXT | STORED);
-        let composer_field = schema_builder.add_text_field("composer", TEXT | STORED);
-        let path_field = schema_builder.add_text_field("path", STORED);
-        let parent_field = schema_builder.add_text_field("parent", STRING | STORED);
-        let index_field = schema_builder.add_u64_field("index", STORED);
-        let schema = schema_builder.build();
+        Self::from_index(Index::create_in_ram(song_schema()))
+    }
+
+    /// Open the index stored in `dir`, or create a new one there
+    pub fn open(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let index = match Index::open_or_create(MmapDirectory::open(dir)?, song_schema()) {
+            Ok(index) => index,
+            Err(e) => {
+                log!("Recreating index in {dir:?}: {e}");
+                std::fs::remove_dir_all(dir)?;
+                std::fs::create_dir_all(dir)?;
+                Index::create_in_dir(dir, song_schema())?
+            }
+        };
+        let indexer = Self::from_index(index)?;
+        let count = indexer.reader.searcher().search(
+            &RangeQuery::new(
+                Bound::Included(Term::from_field_u64(indexer.index_field, 0)),
+                Bound::Unbounded,
+            ),
+            &tantivy::collector::Count,
+        )?;
+        indexer.count.store(count, Ordering::Relaxed);
+        Ok(indexer)
+    }
 
-        let index = Index::create_in_ram(schema.clone());
+    fn from_index(index: Index) -> Result<Self> {
+        index
+            .tokenizers()
+            .register(FOLDING_TOKENIZER, folding_analyzer());
+        let schema = index.schema();
+        let field = |name: &str| schema.get_field(name);
+        let title_field = field("title")?;
+        let composer_field = field("composer")?;
+        let game_field = field("game")?;
+        let format_field = field("format")?;
+        let copyright_field = field("copyright")?;
+        let year_field = field("year")?;
+        let length_field = field("length")?;
+        let ext_field = field("ext")?;
+        let path_field = field("file")?;
+        let path_text_field = field("path")?;
+        let parent_field = field("parent")?;
+        let index_field = field("index")?;
+        let mtime_field = field("mtime")?;
+        let meta_field = field("meta")?;
 
         let index_writer: IndexWriter = index.writer(20_000_000)?;
         let reader = index
@@ -189,11 +393,21 @@ impl SongIndexer {
             reader,
             title_field,
             composer_field,
+            game_field,
+            format_field,
+            copyright_field,
+            year_field,
+            length_field,
+            ext_field,
             path_field,
+            path_text_field,
             parent_field,
             index_field,
-            initial_songs: VecDeque::new(),
+            mtime_field,
+            meta_field,
+            pages: LruCache::new(NonZeroUsize::new(PAGE_CACHE_SIZE).unwrap_or(NonZeroUsize::MIN)),
             count: 0.into(),
+            fuzzy: false,
         })
     }
 
@@ -204,59 +418,208 @@ impl SongIndexer {
             .to_str()
             .context("Illegal parent path")?
             .to_owned();
-        self.index_writer.add_document(doc!(
+        let mut doc = doc!(
                 self.path_field => path.to_str().context("Illegal path")?
                                     .to_owned(),
-                self.parent_field => parent))?;
+                self.parent_field => parent);
+        if let Some(mtime) = dir_mtime_nanos(path) {
+            doc.add_u64(self.mtime_field, mtime);
+        }
+        self.index_writer.add_document(doc)?;
         Ok(())
     }
 
+    /// Find the document for `path`, which may be a song or a directory
+    fn find_path(&self, path: &Path) -> Result<Option<TantivyDocument>> {
+        let searcher = self.reader.searcher();
+        let path_str = path.to_str().context("Illegal path")?;
+        let query = TermQuery::new(
+            Term::from_field_text(self.path_field, path_str),
+            IndexRecordOption::Basic,
+        );
+        let top_docs = searcher.search(&query, &TopDocs::with_limit(1))?;
+        if let Some((_score, doc_address)) = top_docs.first() {
+            return Ok(Some(searcher.doc(*doc_address)?));
+        }
+        Ok(None)
+    }
+
+    pub fn contains(&self, path: &Path) -> Result<bool> {
+        Ok(self.find_path(path)?.is_some())
+    }
+
+    /// True if `dir` is in the index and has not been modified since
+    fn dir_unchanged(&self, dir: &Path) -> Result<bool> {
+        let Some(mtime) = dir_mtime_nanos(dir) else {
+            return Ok(false);
+        };
+        let Some(doc) = self.find_path(dir)? else {
+            return Ok(false);
+        };
+        Ok(get_u64(&doc, self.mtime_field) == Some(mtime))
+    }
+
+    /// Remove the songs in `dir` and the entries for its sub directories.
+    /// Sub directories that no longer exist are removed completely.
+    fn clear_dir(&mut self, dir: &Path) -> Result<()> {
+        for entry in self.browse(dir)? {
+            if entry.file_type == FileType::Dir && !entry.path.is_dir() {
+                self.clear_dir(&entry.path)?;
+            }
+        }
+        let dir_str = dir.to_str().context("Illegal dir path")?;
+        self.index_writer
+            .delete_term(Term::from_field_text(self.parent_field, dir_str));
+        if !dir.is_dir() {
+            self.index_writer
+                .delete_term(Term::from_field_text(self.path_field, dir_str));
+        }
+        // The cached pages would still have the removed songs
+        self.pages.clear();
+        Ok(())
+    }
+
+    /// Replace the entry for `dir` and re-add entries for its sub directories,
+    /// after `clear_dir()`
+    fn update_dir(&mut self, dir: &Path) -> Result<()> {
+        for entry in std::fs::read_dir(dir)?.flatten() {
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                self.add_dir(&entry.path())?;
+            }
+        }
+        self.touch_dir(dir)
+    }
+
+    /// Save the current modification time of `dir`
+    fn touch_dir(&mut self, dir: &Path) -> Result<()> {
+        self.remove_path(dir)?;
+        self.add_dir(dir)
+    }
+
+    /// Remove the song or directory entry for `path`
+    fn remove_path(&mut self, path: &Path) -> Result<()> {
+        let path_str = path.to_str().context("Illegal path")?;
+        self.index_writer
+            .delete_term(Term::from_field_text(self.path_field, path_str));
+        Ok(())
+    }
+
+    /// Remove the song at `path`, returning its number so it can be added
+    /// again under the same number
+    fn remove_song(&mut self, path: &Path) -> Result<Option<usize>> {
+        let index = self.index_of_path(path)?;
+        self.remove_path(path)?;
+        self.pages.clear();
+        Ok(index)
+    }
+
+    /// Fill the holes in the song numbering left by removed songs, by
+    /// moving songs from the end. Must be called after `commit()`, and only
+    /// while indexing added paths, since playlists refer to songs by number.
+    fn compact(&mut self) -> Result<()> {
+        let count = self.count.load(Ordering::Relaxed);
+        let searcher = self.reader.searcher();
+        let mut present = vec![false; count];
+        for segment in searcher.segment_readers() {
+            let column = segment.fast_fields().u64("index")?;
+            for doc in segment.doc_ids_alive() {
+                if let Some(i) = column.first(doc)
+                    && let Some(p) = present.get_mut(i as usize)
+                {
+                    *p = true;
+                }
+            }
+        }
+        let songs = present.iter().filter(|p| **p).count();
+        if songs == count {
+            return Ok(());
+        }
+        log!("Compacting index from {count} to {songs} songs");
+        let holes = (0..songs).filter(|i| !present[*i]);
+        let moved = (songs..count).filter(|i| present[*i]);
+        for (to, from) in holes.zip(moved) {
+            let term = Term::from_field_u64(self.index_field, from as u64);
+            let top_docs = searcher.search(
+                &TermQuery::new(term.clone(), IndexRecordOption::Basic),
+                &TopDocs::with_limit(1),
+            )?;
+            let Some((_score, doc_address)) = top_docs.first() else {
+                continue;
+            };
+            let old: TantivyDocument = searcher.doc(*doc_address)?;
+            let mut doc = TantivyDocument::new();
+            for (field, value) in old.field_values() {
+                if field != self.index_field {
+                    doc.add_field_value(field, &OwnedValue::from(value));
+                }
+            }
+            doc.add_u64(self.index_field, to as u64);
+            self.index_writer.delete_term(term);
+            self.index_writer.add_document(doc)?;
+        }
+        self.count.store(songs, Ordering::Relaxed);
+        self.pages.clear();
+        self.commit()
+    }
+
     pub fn add_song(&mut self, file_info: &FileInfo) -> Result<()> {
         let count = self.count.fetch_add(1, Ordering::Relaxed);
+        self.add_song_as(file_info, count)?;
+        // Keep the song in its page, so it can be found before the next commit
+        let (page, offset) = (count / PAGE_SIZE, count % PAGE_SIZE);
+        if offset == 0 {
+            self.pages.put(page, vec![file_info.clone()]);
+        } else if let Some(songs) = self.pages.peek_mut(&page) {
+            if songs.len() == offset {
+                songs.push(file_info.clone());
+            } else {
+                self.pages.pop(&page);
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the song as number `index`, which must not be in use
+    fn add_song_as(&mut self, file_info: &FileInfo, index: usize) -> Result<()> {
         let title = file_info.get_title();
         let composer = file_info.get("composer");
-        let parent = file_info
-            .path
-            .parent()
-            .unwrap_or(Path::new(""))
+        let parent = archive::parent(&file_info.path)
+            .unwrap_or_default()
             .to_str()
             .context("Illegal parent path")?
             .to_owned();
 
-        self.index_writer.add_document(doc!(
+        let meta = bincode::serialize(&meta_to_cache(&file_info.meta_data))?;
+        let text = |name: &str| match file_info.get(name) {
+            Value::Text(t) => t.as_str(),
+            _ => "",
+        };
+        let path = file_info.path.to_str().context("Illegal path")?;
+        let ext = file_info
+            .path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        let mut doc = doc!(
                 self.title_field => title,
-                self.index_field => count as u64,
+                self.index_field => index as u64,
                 self.composer_field => composer.to_string(),
-                self.path_field => file_info.path.to_str().context("Illegal path")?
-                                    .to_owned(),
-                self.parent_field => parent))?;
-        if self.initial_songs.len() < INITIAL_SONG_COUNT {
-            self.initial_songs.push_back(file_info.clone());
+                self.game_field => text("game"),
+                self.format_field => text("format"),
+                self.copyright_field => text("copyright"),
+                self.ext_field => ext,
+                self.path_field => path,
+                self.path_text_field => path,
+                self.parent_field => parent,
+                self.meta_field => meta);
+        if let Value::Number(year) = file_info.get("year") {
+            doc.add_u64(self.year_field, *year as u64);
         }
-        Ok(())
-    }
-
-    fn add_cached_song(&mut self, cached: &CachedFileInfo) -> Result<()> {
-        let count = self.count.fetch_add(1, Ordering::Relaxed);
-        let parent = cached
-            .path
-            .parent()
-            .unwrap_or(Path::new(""))
-            .to_str()
-            .context("Illegal parent path")?
-            .to_owned();
-
-        self.index_writer.add_document(doc!(
-                self.title_field => cached.title.as_str(),
-                self.index_field => count as u64,
-                self.composer_field => cached.composer.as_str(),
-                self.path_field => cached.path.to_str().context("Illegal path")?
-                                    .to_owned(),
-                self.parent_field => parent))?;
-        if self.initial_songs.len() < INITIAL_SONG_COUNT {
-            self.initial_songs
-                .push_back(cached.clone().into_file_info());
+        if let Value::Number(length) = file_info.get("length") {
+            doc.add_u64(self.length_field, *length as u64);
         }
+        self.index_writer.add_document(doc)?;
         Ok(())
     }
 
@@ -282,6 +645,7 @@ impl SongIndexer {
             return Some(SongInfo {
                 title: title.to_string(),
                 composer: segments[1].to_owned(),
+                format: segments[2].to_owned(),
                 ..SongInfo::default()
             });
         } else if l >= 4 && MODLAND_FORMATS.contains(&segments[3]) {
@@ -291,6 +655,7 @@ impl SongIndexer {
                 return Some(SongInfo {
                     title: title.to_string(),
                     composer: format!("{composer} + {coop}"),
+                    format: segments[3].to_owned(),
                     ..SongInfo::default()
                 });
             }
@@ -298,19 +663,26 @@ impl SongIndexer {
                 title: title.to_string(),
                 game: segments[1].to_owned(),
                 composer: segments[2].to_owned(),
-                ..SongInfo::default()
+                format: segments[3].to_owned(),
             });
         }
         None
     }
 
-    fn identify_song_internal(path: &Path) -> Result<Option<SongInfo>> {
+    /// Identify a song by reading its header. Meta data that does not fit
+    /// in `SongInfo` is added to `meta_data`.
+    fn identify_song_internal(
+        path: &Path,
+        meta_data: &mut HashMap<String, Value>,
+    ) -> Result<Option<SongInfo>> {
         if let Some(ext) = path.extension() {
             if ext == "sid" {
-                let mut buf: [u8; 0x60] = [0; 0x60];
+                let mut buf: [u8; 0x76] = [0; 0x76];
                 File::open(path)?.read_exact(&mut buf)?;
                 let title = slice_to_string(&buf[0x16..0x36]);
                 let composer = slice_to_string(&buf[0x36..0x56]);
+                let released = slice_to_string(&buf[0x56..0x76]);
+                meta_data.insert("copyright".into(), released.into());
                 return Ok(Some(SongInfo {
                     title,
                     composer,
@@ -328,6 +700,9 @@ impl SongIndexer {
                 if let Some(title) = tag.title() {
                     info.title = title.into();
                 }
+                if let Some(year) = tag.year() {
+                    meta_data.insert("year".into(), year.into());
+                }
                 return Ok(Some(info));
             }
         }
@@ -339,10 +714,75 @@ impl SongIndexer {
         Ok(res)
     }
 
+    /// Add `length` (of the default subtune) and, for songs with more than
+    /// one subtune, `lengths` (all subtunes, space separated) in seconds.
+    fn add_lengths(path: &Path, meta_data: &mut HashMap<String, Value>) {
+        let ext = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let (lengths, start) = match ext.as_str() {
+            "sid" => sid_lengths(path).unwrap_or_default(),
+            "mp3" => match mp3_duration::from_path(path) {
+                Ok(duration) => (vec![duration.as_secs_f64()], 0),
+                Err(_) => return,
+            },
+            _ => return,
+        };
+        if let Some(length) = lengths.get(start).or(lengths.first()) {
+            meta_data.insert("length".into(), Value::Number(*length));
+        }
+        if lengths.len() > 1 {
+            let text = lengths.iter().map(f64::to_string).join(" ");
+            meta_data.insert("lengths".into(), Value::Text(text));
+        }
+    }
+
+    /// Identify the songs inside the archive at `path`, by unpacking it
+    /// to a temporary directory
+    pub fn identify_archive(path: &Path) -> Result<Vec<FileInfo>> {
+        let dest = std::env::temp_dir().join(format!("oldplay-index-{}", std::process::id()));
+        let added = std::fs::metadata(path)
+            .and_then(|md| md.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok());
+        // Only unpack the songs, and the files they need
+        let names = archive::list(path)?;
+        let wanted: Vec<&String> = names
+            .iter()
+            .filter(|name| is_song_file(&dest.join(name)).unwrap_or(false))
+            .collect();
+        let songs = archive::unpack(path, &dest, |name| {
+            wanted
+                .iter()
+                .any(|song| *song == name || archive::is_companion(song, name))
+        })
+        .map(|unpacked| {
+            unpacked
+                .iter()
+                .filter(|name| wanted.contains(name))
+                .map(|name| {
+                    let mut song = SongIndexer::identify_song(&dest.join(name));
+                    song.path = archive::join(path, name);
+                    if let Some(added) = added {
+                        song.meta_data
+                            .insert("added".into(), Value::Number(added.as_secs() as f64));
+                    }
+                    song
+                })
+                .collect()
+        });
+        let _ = std::fs::remove_dir_all(&dest);
+        songs
+    }
+
     pub fn identify_song(path: &Path) -> FileInfo {
         let mut meta_data: HashMap<String, Value> = HashMap::new();
-        let info = SongIndexer::parse_modland_info(path)
-            .or_else(|| SongIndexer::identify_song_internal(path).ok().flatten());
+        let info = SongIndexer::parse_modland_info(path).or_else(|| {
+            SongIndexer::identify_song_internal(path, &mut meta_data)
+                .ok()
+                .flatten()
+        });
 
         if let Some(info) = info {
             meta_data.insert("title".into(), info.title.into());
@@ -356,8 +796,23 @@ impl SongIndexer {
                 .unwrap_or_default();
             meta_data.insert("title".into(), title.into());
         }
+        if !meta_data.contains_key("year")
+            && let Some(Value::Text(copyright)) = meta_data.get("copyright")
+            && let Some(year) = year_from_text(copyright)
+        {
+            meta_data.insert("year".into(), Value::Number(f64::from(year)));
+        }
+        SongIndexer::add_lengths(path, &mut meta_data);
         if let Ok(md) = std::fs::metadata(path) {
             meta_data.insert("size".into(), Value::Number(md.len() as f64));
+            // When the file was added, as far as we can tell
+            if let Some(added) = md
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            {
+                meta_data.insert("added".into(), Value::Number(added.as_secs() as f64));
+            }
         }
         let meta_path = path.with_extension(format!(
             "{}.meta",
@@ -403,12 +858,19 @@ impl SongIndexer {
         let composer = get_value(doc, self.composer_field);
 
         let has_title = title.is_some();
-        let mut meta_data = HashMap::new();
+        let mut meta_data = doc
+            .get_first(self.meta_field)
+            .and_then(|v| match OwnedValue::from(v) {
+                OwnedValue::Bytes(bytes) => bincode::deserialize::<CachedMeta>(&bytes).ok(),
+                _ => None,
+            })
+            .map(meta_from_cache)
+            .unwrap_or_default();
         if let Some(title) = title {
-            meta_data.insert("title".to_owned(), title);
+            meta_data.entry("title".to_owned()).or_insert(title);
         }
         if let Some(composer) = composer {
-            meta_data.insert("composer".to_owned(), composer);
+            meta_data.entry("composer".to_owned()).or_insert(composer);
         }
 
         Ok(if has_title {
@@ -426,13 +888,95 @@ impl SongIndexer {
         })
     }
 
+    /// Fields searched by terms without a field name
+    fn default_fields(&self) -> Vec<Field> {
+        vec![self.title_field, self.composer_field, self.game_field]
+    }
+
+    fn parse_query(&self, query: &str) -> Result<Box<dyn Query>> {
+        let mut query_parser = QueryParser::for_index(&self.index, self.default_fields());
+        query_parser.set_conjunction_by_default();
+        Ok(query_parser.parse_query(query)?)
+    }
+
+    /// The text to do a fuzzy search for, if `query` should be fuzzy. A
+    /// query starting with `~` always is.
+    fn fuzzy_text<'a>(&self, query: &'a str) -> Option<&'a str> {
+        query
+            .strip_prefix('~')
+            .or_else(|| self.fuzzy.then_some(query))
+    }
+
+    /// A query where each word can match words that are a few letters off.
+    /// With `prefix_last`, the last word can also match the start of words.
+    fn fuzzy_query(&self, text: &str, prefix_last: bool) -> Result<Box<dyn Query>> {
+        let words = fold_words(text);
+        if words.is_empty() {
+            return Err(anyhow!("Nothing to search for"));
+        }
+        let last = words.len() - 1;
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        for (i, word) in words.iter().enumerate() {
+            let distance = fuzzy_distance(word);
+            let mut alternatives: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+            for field in self.default_fields() {
+                let term = Term::from_field_text(field, word);
+                let query = if prefix_last && i == last {
+                    FuzzyTermQuery::new_prefix(term, distance, true)
+                } else {
+                    FuzzyTermQuery::new(term, distance, true)
+                };
+                alternatives.push((Occur::Should, Box::new(query)));
+            }
+            clauses.push((Occur::Must, Box::new(BooleanQuery::new(alternatives))));
+        }
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+
+    pub fn set_fuzzy(&mut self, fuzzy: bool) {
+        self.fuzzy = fuzzy;
+    }
+
     pub fn search(&mut self, query: &str) -> Result<Vec<FileInfo>> {
+        let query = match self.fuzzy_text(query) {
+            Some(text) => self.fuzzy_query(text, false)?,
+            None => self.parse_query(query)?,
+        };
+        self.search_query(query.as_ref(), 100_000)
+    }
+
+    /// Search while the query is still being typed; the last word also
+    /// matches words that start with it.
+    pub fn search_prefix(&mut self, query: &str, limit: usize) -> Result<Vec<FileInfo>> {
+        if let Some(text) = self.fuzzy_text(query) {
+            let prefix_last = !text.ends_with(char::is_whitespace);
+            let query = self.fuzzy_query(text, prefix_last)?;
+            return self.search_query(query.as_ref(), limit);
+        }
+        let start = query.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let (rest, last) = query.split_at(start);
+        let word = fold_words(last).pop().unwrap_or_default();
+        if word.is_empty() || !last.chars().all(char::is_alphanumeric) {
+            let query = self.parse_query(query)?;
+            return self.search_query(query.as_ref(), limit);
+        }
+        let pattern = format!("{}.*", regex::escape(&word));
+        let mut prefix_queries: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        for field in self.default_fields() {
+            let query = RegexQuery::from_pattern(&pattern, field)?;
+            prefix_queries.push((Occur::Should, Box::new(query)));
+        }
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> =
+            vec![(Occur::Must, Box::new(BooleanQuery::new(prefix_queries)))];
+        if !rest.trim().is_empty() {
+            clauses.push((Occur::Must, self.parse_query(rest)?));
+        }
+        self.search_query(&BooleanQuery::new(clauses), limit)
+    }
+
+    fn search_query(&self, query: &dyn Query, limit: usize) -> Result<Vec<FileInfo>> {
         let searcher = self.reader.searcher();
-        let mut query_parser =
-            QueryParser::for_index(&self.index, vec![self.title_field, self.composer_field]);
-        query_parser.set_conjunction_by_default();
-        let query = query_parser.parse_query(query)?;
-        let top_docs = searcher.search(&query, &TopDocs::with_limit(100_000))?;
+        let top_docs = searcher.search(query, &TopDocs::with_limit(limit))?;
 
         let mut result = Vec::new();
         for (_score, doc_address) in top_docs {
@@ -445,22 +989,74 @@ impl SongIndexer {
         Ok(result)
     }
 
-    pub fn search_by_index_range(&mut self, start: u64, end: u64) -> Result<()> {
+    /// Get the songs numbered `start` to `end` (exclusive), in index order.
+    /// Songs that are not committed yet are missing.
+    pub fn search_by_index_range(&self, start: u64, end: u64) -> Result<Vec<FileInfo>> {
         let searcher = self.reader.searcher();
         let lower = Term::from_field_u64(self.index_field, start);
         let upper = Term::from_field_u64(self.index_field, end);
-        let query = RangeQuery::new(Bound::Included(lower), Bound::Included(upper));
-        let top_docs = searcher.search(&query, &TopDocs::with_limit(100_000))?;
+        let query = RangeQuery::new(Bound::Included(lower), Bound::Excluded(upper));
+        let limit = usize::try_from(end.saturating_sub(start))?.max(1);
+        let top_docs = searcher.search(&query, &TopDocs::with_limit(limit))?;
 
         let mut result = Vec::new();
         for (_score, doc_address) in top_docs {
             let doc: TantivyDocument = searcher.doc(doc_address)?;
-            let file_info = self.doc_to_fileinfo(&doc)?;
-            if file_info.file_type == FileType::Song {
-                result.push(file_info);
+            let index = get_u64(&doc, self.index_field);
+            result.push((index, self.doc_to_fileinfo(&doc)?));
+        }
+        result.sort_by_key(|(index, _)| *index);
+        Ok(result.into_iter().map(|(_, song)| song).collect())
+    }
+
+    /// Get song number `index`, reading its whole page from the index if
+    /// it is not cached
+    fn song_at(&mut self, index: usize) -> Option<FileInfo> {
+        let page = index / PAGE_SIZE;
+        if let Some(songs) = self.pages.get(&page) {
+            return songs.get(index % PAGE_SIZE).cloned();
+        }
+        let start = page * PAGE_SIZE;
+        let end = (start + PAGE_SIZE).min(self.count.load(Ordering::Relaxed));
+        let songs = self
+            .search_by_index_range(start as u64, end as u64)
+            .inspect_err(|e| log!("Could not read songs {start}-{end}: {e}"))
+            .ok()?;
+        if songs.len() < end.saturating_sub(start) {
+            // Some songs are not committed yet, so read this page again later
+            return self.get_song(index).ok().flatten();
+        }
+        let song = songs.get(index % PAGE_SIZE).cloned();
+        self.pages.put(page, songs);
+        song
+    }
+
+    /// The index number of the song at `path`
+    fn index_of_path(&self, path: &Path) -> Result<Option<usize>> {
+        for (page, songs) in &self.pages {
+            if let Some(i) = songs.iter().position(|s| s.path == path) {
+                return Ok(Some(page * PAGE_SIZE + i));
             }
         }
-        Ok(())
+        Ok(self
+            .find_path(path)?
+            .and_then(|doc| get_u64(&doc, self.index_field))
+            .map(|i| i as usize))
+    }
+
+    /// Get the song that was added as number `index`
+    pub fn get_song(&self, index: usize) -> Result<Option<FileInfo>> {
+        let searcher = self.reader.searcher();
+        let query = TermQuery::new(
+            Term::from_field_u64(self.index_field, index as u64),
+            IndexRecordOption::Basic,
+        );
+        let top_docs = searcher.search(&query, &TopDocs::with_limit(1))?;
+        if let Some((_score, doc_address)) = top_docs.first() {
+            let doc: TantivyDocument = searcher.doc(*doc_address)?;
+            return Ok(Some(self.doc_to_fileinfo(&doc)?));
+        }
+        Ok(None)
     }
 
     pub fn browse(&self, dir: &Path) -> Result<Vec<FileInfo>> {
@@ -499,22 +1095,24 @@ pub struct IndexedSongs {
 impl SongCollection for IndexedSongs {
     fn get(&self, index: usize) -> FileInfo {
         let mut i = self.indexer.lock().unwrap();
-        let _ = i.search_by_index_range(0, 100);
-        i.initial_songs[index].clone()
+        // Songs not committed yet can not be found
+        i.song_at(index).unwrap_or_default()
+    }
+
+    fn get_range(&self, start: usize, end: usize) -> Vec<FileInfo> {
+        let mut i = self.indexer.lock().unwrap();
+        (start..end)
+            .map(|index| i.song_at(index).unwrap_or_default())
+            .collect()
     }
+
     fn index_of(&self, song: &FileInfo) -> Option<usize> {
         let i = self.indexer.lock().unwrap();
-        for (i, s) in i.initial_songs.iter().enumerate() {
-            if song.path() == s.path() {
-                return Some(i);
-            }
-        }
-        None
+        i.index_of_path(song.path()).ok().flatten()
     }
 
     fn len(&self) -> usize {
         let i = self.indexer.lock().unwrap();
-        //i.initial_songs.len()
         i.count.load(Ordering::Relaxed)
     }
 }
@@ -524,6 +1122,7 @@ pub struct RemoteSongIndexer {
     sender: mpsc::Sender<Cmd>,
     index_thread: Option<JoinHandle<()>>,
     is_working: Arc<AtomicBool>,
+    changed_dirs: Arc<Mutex<Vec<PathBuf>>>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -543,6 +1142,27 @@ impl Drop for RemoteSongIndexer {
     }
 }
 
+/// File extensions found among songs that are never songs themselves
+static NON_SONGS: LazyLock<HashSet<&'static str>> = LazyLock::new(|| {
+    [
+        "d71", "d81", "dfi", "d64", "1st", "exe", "hvs", "txt", "faq", "md5",
+    ]
+    .into()
+});
+
+/// How long watched directories must be quiet before changes are indexed
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+fn is_song_file(path: &Path) -> Result<bool> {
+    if let Some(ext) = path.extension() {
+        let ext = ext.to_string_lossy().to_lowercase();
+        if NON_SONGS.contains(ext.as_str()) {
+            return Ok(false);
+        }
+    }
+    Ok(musix::can_handle(path)?)
+}
+
 impl RemoteSongIndexer {
     #[inline]
     #[allow(clippy::unwrap_used)]
@@ -553,110 +1173,349 @@ impl RemoteSongIndexer {
     fn run(
         indexer: &Arc<Mutex<SongIndexer>>,
         working: &Arc<AtomicBool>,
+        changed_dirs: &Arc<Mutex<Vec<PathBuf>>>,
         rx: &Receiver<Cmd>,
     ) -> Result<()> {
-        let non_songs: HashSet<&str> = [
-            "d71", "d81", "dfi", "d64", "1st", "exe", "hvs", "txt", "faq", "md5",
-        ]
-        .into();
-
-        #[allow(clippy::unwrap_used)]
-        let lock = || indexer.lock().unwrap();
-
         let mut ignore_cache: bool = false;
+        let mut watcher = DirWatcher::new()
+            .inspect_err(|e| log!("Not watching for file changes: {e}"))
+            .ok();
+        let mut changes: Vec<Change> = Vec::new();
 
         loop {
-            let cmd = rx.recv()?;
+            let cmd = match rx.recv_timeout(WATCH_INTERVAL) {
+                Ok(cmd) => cmd,
+                Err(RecvTimeoutError::Timeout) => {
+                    let Some(watcher) = &mut watcher else {
+                        continue;
+                    };
+                    let new_changes = watcher.changes();
+                    if new_changes.is_empty() && !changes.is_empty() {
+                        working.store(true, Ordering::Relaxed);
+                        match Self::apply_changes(indexer, working, &changes, watcher) {
+                            #[allow(clippy::unwrap_used)]
+                            Ok(dirs) => changed_dirs.lock().unwrap().extend(dirs),
+                            Err(e) => log!("Could not index changed files: {e}"),
+                        }
+                        changes.clear();
+                        working.store(false, Ordering::Relaxed);
+                    }
+                    changes.extend(new_changes);
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break Ok(()),
+            };
             match cmd {
                 Cmd::IgnoreCache(ignore) => ignore_cache = ignore,
                 Cmd::Quit => {
                     break Ok(());
                 }
                 Cmd::AddPath(path) => {
-                    let mut now = Instant::now();
-                    let cache_base = cache_base_dir();
                     working.store(true, Ordering::Relaxed);
+                    Self::index_tree(indexer, &path, working, ignore_cache, watcher.as_mut())?;
+                    #[allow(clippy::unwrap_used)]
+                    indexer.lock().unwrap().compact()?;
+                    working.store(false, Ordering::Relaxed);
+                }
+            }
+        }
+    }
 
-                    // With contents_first(true), all files in a directory are
-                    // yielded before the directory entry itself.
-                    let mut pending_files: Vec<walkdir::DirEntry> = Vec::new();
+    /// Index all songs under `path`, and watch its directories for changes
+    fn index_tree(
+        indexer: &Arc<Mutex<SongIndexer>>,
+        path: &Path,
+        working: &Arc<AtomicBool>,
+        ignore_cache: bool,
+        mut watcher: Option<&mut DirWatcher>,
+    ) -> Result<()> {
+        #[allow(clippy::unwrap_used)]
+        let lock = || indexer.lock().unwrap();
 
-                    for entry in WalkDir::new(path).contents_first(true) {
-                        if !working.load(Ordering::Relaxed) {
-                            break;
-                        }
-                        let p = entry?;
-
-                        if p.file_type().is_dir() {
-                            if !ignore_cache
-                                && let Some(ref base) = cache_base
-                                && let Some(cached) = load_cache(base, p.path())
-                            {
-                                // Cache hit: add directly to Tantivy
-                                for cf in &cached.files {
-                                    lock().add_cached_song(cf)?;
-                                }
-                            } else {
-                                // Cache miss: identify songs and save cache
-                                let mut cache_entries = Vec::new();
-                                for file_entry in pending_files.drain(..) {
-                                    if let Some(ext) = file_entry.path().extension() {
-                                        let ext = ext.to_string_lossy().to_lowercase();
-                                        if non_songs.contains(ext.as_str()) {
-                                            continue;
-                                        }
-                                    }
-                                    if file_entry.file_type().is_file()
-                                        && musix::can_handle(file_entry.path())?
-                                    {
-                                        let file_info =
-                                            SongIndexer::identify_song(file_entry.path());
-                                        lock().add_song(&file_info)?;
-                                        cache_entries
-                                            .push(CachedFileInfo::from_file_info(&file_info));
-                                    }
-                                }
-                                if let Some(ref base) = cache_base
-                                    && let Some((secs, nanos)) = dir_mtime(p.path())
-                                {
-                                    let cache = DirCache {
-                                        mtime_secs: secs,
-                                        mtime_nanos: nanos,
-                                        files: cache_entries,
-                                    };
-                                    save_cache(base, p.path(), &cache);
-                                }
-                            }
-                            pending_files.clear();
-                            lock().add_dir(p.path())?;
-                        } else {
-                            pending_files.push(p);
+        let mut now = Instant::now();
+        let cache_base = cache_base_dir();
+
+        // With contents_first(true), all files in a directory are
+        // yielded before the directory entry itself.
+        let mut pending_files: HashMap<PathBuf, Vec<walkdir::DirEntry>> = HashMap::new();
+
+        for entry in WalkDir::new(path).contents_first(true) {
+            if !working.load(Ordering::Relaxed) {
+                break;
+            }
+            let p = entry?;
+
+            if p.file_type().is_dir() {
+                if let Some(watcher) = watcher.as_deref_mut() {
+                    let _ = watcher
+                        .watch(p.path())
+                        .inspect_err(|e| log!("Not watching for changes: {e}"));
+                }
+                let files = pending_files.remove(p.path()).unwrap_or_default();
+                if !ignore_cache && lock().dir_unchanged(p.path())? {
+                    // Songs are still in the index from last time
+                    continue;
+                }
+                lock().clear_dir(p.path())?;
+                if !ignore_cache
+                    && let Some(ref base) = cache_base
+                    && let Some(cached) = load_cache(base, p.path())
+                {
+                    // Cache hit: add directly to Tantivy
+                    let mut archives = HashSet::new();
+                    for cf in cached.files {
+                        let file_info = cf.into_file_info();
+                        if let Some((archive, _)) = archive::split(&file_info.path)
+                            && archives.insert(archive.clone())
+                        {
+                            lock().add_dir(&archive)?;
                         }
+                        lock().add_song(&file_info)?;
+                    }
+                } else {
+                    // Cache miss: identify songs and save cache
+                    let files = files
+                        .into_iter()
+                        .filter(|f| f.file_type().is_file())
+                        .map(walkdir::DirEntry::into_path)
+                        .collect();
+                    Self::index_files(indexer, p.path(), files, HashMap::new())?;
+                }
+                lock().update_dir(p.path())?;
+            } else {
+                let parent = p.path().parent().unwrap_or(Path::new(""));
+                pending_files.entry(parent.to_owned()).or_default().push(p);
+            }
+
+            if now.elapsed() > Duration::from_millis(1000) {
+                lock().commit()?;
+                now += Duration::from_millis(1000);
+            }
+        }
+        // Songs given directly, not found by walking a directory
+        for file_entry in pending_files.into_values().flatten() {
+            if file_entry.file_type().is_file()
+                && musix::can_handle(file_entry.path())?
+                && !lock().contains(file_entry.path())?
+            {
+                lock().add_path(file_entry.path())?;
+            }
+        }
+        lock().commit()
+    }
+
+    /// Add the songs among `files` in `dir` to the index and save the dir
+    /// cache. Songs in `known` are added as they are, the rest are identified.
+    fn index_files(
+        indexer: &Arc<Mutex<SongIndexer>>,
+        dir: &Path,
+        files: Vec<PathBuf>,
+        mut known: HashMap<PathBuf, FileInfo>,
+    ) -> Result<()> {
+        let mut cache_entries = Vec::new();
+        for path in files {
+            if archive::is_archive(&path) {
+                let songs = Self::archive_songs(&path, &mut known);
+                if !songs.is_empty() {
+                    #[allow(clippy::unwrap_used)]
+                    indexer.lock().unwrap().add_dir(&path)?;
+                }
+                for file_info in songs {
+                    #[allow(clippy::unwrap_used)]
+                    indexer.lock().unwrap().add_song(&file_info)?;
+                    cache_entries.push(CachedFileInfo::from_file_info(&file_info));
+                }
+                continue;
+            }
+            let file_info = match known.remove(&path) {
+                Some(file_info) => file_info,
+                None if is_song_file(&path)? => SongIndexer::identify_song(&path),
+                None => continue,
+            };
+            #[allow(clippy::unwrap_used)]
+            indexer.lock().unwrap().add_song(&file_info)?;
+            cache_entries.push(CachedFileInfo::from_file_info(&file_info));
+        }
+        if let Some(base) = cache_base_dir()
+            && let Some((secs, nanos)) = dir_mtime(dir)
+        {
+            let cache = DirCache {
+                mtime_secs: secs,
+                mtime_nanos: nanos,
+                files: cache_entries,
+            };
+            save_cache(&base, dir, &cache);
+        }
+        Ok(())
+    }
 
-                        if now.elapsed() > Duration::from_millis(1000) {
-                            lock().commit()?;
-                            now += Duration::from_millis(1000);
+    /// The songs inside `archive`, taken from `known` if they are there
+    fn archive_songs(archive: &Path, known: &mut HashMap<PathBuf, FileInfo>) -> Vec<FileInfo> {
+        let inside: Vec<PathBuf> = known
+            .keys()
+            .filter(|p| archive::split(p).is_some_and(|(a, _)| a == archive))
+            .cloned()
+            .collect();
+        if !inside.is_empty() {
+            return inside.iter().filter_map(|p| known.remove(p)).collect();
+        }
+        SongIndexer::identify_archive(archive)
+            .inspect_err(|e| log!("Could not read {archive:?}: {e}"))
+            .unwrap_or_default()
+    }
+
+    /// Save the dir cache for `dir` from the songs in the index
+    fn save_dir_cache(indexer: &Arc<Mutex<SongIndexer>>, dir: &Path) -> Result<()> {
+        #[allow(clippy::unwrap_used)]
+        let lock = || indexer.lock().unwrap();
+
+        let (Some(base), Some((secs, nanos))) = (cache_base_dir(), dir_mtime(dir)) else {
+            return Ok(());
+        };
+        let mut files = Vec::new();
+        let entries = lock().browse(dir)?;
+        for entry in entries {
+            if entry.file_type == FileType::Song {
+                files.push(CachedFileInfo::from_file_info(&entry));
+            } else if archive::is_archive(&entry.path) {
+                let songs = lock().browse(&entry.path)?;
+                files.extend(songs.iter().map(CachedFileInfo::from_file_info));
+            }
+        }
+        let cache = DirCache {
+            mtime_secs: secs,
+            mtime_nanos: nanos,
+            files,
+        };
+        save_cache(&base, dir, &cache);
+        Ok(())
+    }
+
+    /// Update the index after files changed in watched directories.
+    /// Returns the directories whose contents changed.
+    ///
+    /// Only the changed files are updated. Songs keep their numbers, since
+    /// playlists of all songs refer to them by number, so removed songs
+    /// leave holes until the index is compacted on the next start.
+    fn apply_changes(
+        indexer: &Arc<Mutex<SongIndexer>>,
+        working: &Arc<AtomicBool>,
+        changes: &[Change],
+        watcher: &mut DirWatcher,
+    ) -> Result<Vec<PathBuf>> {
+        #[allow(clippy::unwrap_used)]
+        let lock = || indexer.lock().unwrap();
+
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        for change in changes.iter() {
+            let Some(parent) = change.path().parent() else {
+                continue;
+            };
+            if !dirs.iter().any(|d| d == parent) {
+                dirs.push(parent.to_owned());
+            }
+            if let Change::DirCreated(path) = change {
+                Self::index_tree(indexer, path, working, false, Some(watcher))?;
+            }
+        }
+        for dir in &dirs {
+            if !dir.is_dir() {
+                // Removed, along with its parent's entry for it
+                continue;
+            }
+            log!("Updating changed directory {dir:?}");
+            let changed: Vec<&Path> = changes
+                .iter()
+                .map(Change::path)
+                .filter(|p| p.parent() == Some(dir.as_path()))
+                .sorted()
+                .dedup()
+                .collect();
+            let mut numbers = HashMap::new();
+            let entries = lock().browse(dir)?;
+            for entry in entries {
+                if !changed.contains(&entry.path.as_path()) {
+                    continue;
+                }
+                if entry.file_type == FileType::Song {
+                    if let Some(index) = lock().remove_song(&entry.path)? {
+                        numbers.insert(entry.path, index);
+                    }
+                } else if archive::is_archive(&entry.path) {
+                    let songs = lock().browse(&entry.path)?;
+                    for song in songs {
+                        if let Some(index) = lock().remove_song(&song.path)? {
+                            numbers.insert(song.path, index);
                         }
                     }
-                    lock().commit()?;
-                    working.store(false, Ordering::Relaxed);
+                    lock().remove_path(&entry.path)?;
+                } else if !entry.path.is_dir() {
+                    lock().clear_dir(&entry.path)?;
                 }
             }
+            for path in changed.into_iter().filter(|p| p.is_file()) {
+                let songs = if archive::is_archive(path) {
+                    let songs = Self::archive_songs(path, &mut HashMap::new());
+                    if !songs.is_empty() {
+                        lock().add_dir(path)?;
+                    }
+                    songs
+                } else if is_song_file(path)? {
+                    vec![SongIndexer::identify_song(path)]
+                } else {
+                    continue;
+                };
+                for song in songs {
+                    match numbers.get(&song.path) {
+                        Some(&index) => lock().add_song_as(&song, index)?,
+                        None => lock().add_song(&song)?,
+                    }
+                }
+            }
+            lock().touch_dir(dir)?;
+        }
+        lock().commit()?;
+        for dir in dirs.iter().filter(|d| d.is_dir()) {
+            Self::save_dir_cache(indexer, dir)?;
         }
+        Ok(dirs)
     }
 
+    /// Create an indexer that keeps everything in memory
     pub fn new() -> Result<RemoteSongIndexer> {
-        let indexer = Arc::new(Mutex::new(SongIndexer::new()?));
+        Self::start(SongIndexer::new()?)
+    }
+
+    /// Create an indexer for songs under `roots`, stored in the cache dir
+    /// so it is available directly on the next start with the same roots.
+    pub fn open(roots: &[PathBuf]) -> Result<RemoteSongIndexer> {
+        let persistent = cache_base_dir()
+            .map(|base| index_dir_for_roots(&base, roots))
+            .and_then(|dir| {
+                SongIndexer::open(&dir)
+                    .inspect_err(|e| log!("Could not open index in {dir:?}: {e}"))
+                    .ok()
+            });
+        match persistent {
+            Some(indexer) => Self::start(indexer),
+            None => Self::new(),
+        }
+    }
+
+    fn start(indexer: SongIndexer) -> Result<RemoteSongIndexer> {
+        let indexer = Arc::new(Mutex::new(indexer));
         let (sender, rx) = mpsc::channel::<Cmd>();
 
         let working = Arc::new(AtomicBool::new(false));
+        let changed_dirs = Arc::new(Mutex::new(Vec::new()));
         let index_thread = Some({
             let indexer = indexer.clone();
             let working = working.clone();
+            let changed_dirs = changed_dirs.clone();
             thread::Builder::new()
                 .name("index_thread".into())
                 .spawn(move || {
-                    RemoteSongIndexer::run(&indexer, &working, &rx).expect("Fail");
+                    RemoteSongIndexer::run(&indexer, &working, &changed_dirs, &rx).expect("Fail");
                 })?
         });
         Ok(RemoteSongIndexer {
@@ -664,6 +1523,7 @@ impl RemoteSongIndexer {
             sender,
             index_thread,
             is_working: working,
+            changed_dirs,
         })
     }
 
@@ -683,10 +1543,18 @@ impl RemoteSongIndexer {
         indexer.search(query)
     }
 
-    pub fn search_by_index_range(&mut self, start: u64, end: u64) -> Result<()> {
+    pub fn search_prefix(&mut self, query: &str, limit: usize) -> Result<Vec<FileInfo>> {
         let mut indexer = self.lock();
-        indexer.search_by_index_range(start, end)?;
-        Ok(())
+        indexer.search_prefix(query, limit)
+    }
+
+    /// Make all searches typo tolerant, not just those starting with `~`
+    pub fn set_fuzzy(&self, fuzzy: bool) {
+        self.lock().set_fuzzy(fuzzy);
+    }
+
+    pub fn search_by_index_range(&self, start: u64, end: u64) -> Result<Vec<FileInfo>> {
+        self.lock().search_by_index_range(start, end)
     }
 
     pub fn browse(&self, dir: &Path) -> Result<Vec<FileInfo>> {
@@ -709,27 +1577,78 @@ impl RemoteSongIndexer {
     pub(crate) fn working(&self) -> bool {
         self.is_working.load(Ordering::Relaxed)
     }
+
+    /// Directories that were updated because files in them changed, since
+    /// the last call
+    #[allow(clippy::unwrap_used)]
+    pub(crate) fn take_changed_dirs(&self) -> Vec<PathBuf> {
+        std::mem::take(&mut *self.changed_dirs.lock().unwrap())
+    }
 }
 
 #[cfg(test)]
 #[allow(clippy::unwrap_used)]
 mod tests {
+    use std::collections::HashMap;
     use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
 
     use walkdir::WalkDir;
 
+    use std::sync::atomic::Ordering;
+
+    use tantivy::Term;
+
+    use crate::archive;
     use crate::player::init_music;
     use crate::rustplay::indexer::RemoteSongIndexer;
-    use crate::rustplay::song::{FileInfo, FileType};
+    use crate::rustplay::song::{FileInfo, FileType, SongCollection};
+    use crate::value::Value;
 
-    use super::SongIndexer;
+    use super::{
+        CachedFileInfo, DirCache, IndexedSongs, SongIndexer, cache_file_for_dir, dir_mtime,
+        load_cache, save_cache, year_from_text,
+    };
 
     fn identify_works() {
         let path: PathBuf = "music/C64/Ark_Pandora.sid".into();
-        let info = SongIndexer::identify_song_internal(&path).unwrap().unwrap();
+        let mut meta_data = HashMap::new();
+        let info = SongIndexer::identify_song_internal(&path, &mut meta_data)
+            .unwrap()
+            .unwrap();
         assert_eq!(info.title, "Ark Pandora");
     }
 
+    #[test]
+    fn year_is_found_in_copyright() {
+        assert_eq!(year_from_text("1987 Thalamus"), Some(1987));
+        assert_eq!(year_from_text("(C) 2003 Someone"), Some(2003));
+        assert_eq!(year_from_text("198? Ocean"), None);
+    }
+
+    #[test]
+    fn fuzzy_search_works() {
+        let mut indexer = SongIndexer::new().unwrap();
+        for (title, composer) in [("Turrican", "Chris Hülsbeck"), ("Commando", "Rob Hubbard")] {
+            let mut song = FileInfo {
+                path: PathBuf::from(format!("{title}.mod")),
+                ..FileInfo::default()
+            };
+            song.meta_data.insert("title".into(), title.into());
+            song.meta_data.insert("composer".into(), composer.into());
+            indexer.add_song(&song).unwrap();
+        }
+        indexer.commit().unwrap();
+
+        assert_eq!(indexer.search("hulsbeck").unwrap().len(), 1);
+        assert!(indexer.search("huelsbeck").unwrap().is_empty());
+        assert_eq!(indexer.search("~huelsbeck").unwrap().len(), 1);
+        assert_eq!(indexer.search("~hubard comando").unwrap().len(), 1);
+        assert_eq!(indexer.search_prefix("~turic", 10).unwrap().len(), 1);
+        indexer.set_fuzzy(true);
+        assert_eq!(indexer.search("turican").unwrap().len(), 1);
+    }
+
     #[test]
     fn normal_search_works() {
         init_music();
@@ -762,6 +1681,18 @@ mod tests {
         assert!(result.len() >= 3);
         let result = indexer.search("xywizoqp").unwrap();
         assert!(result.is_empty());
+
+        let result = indexer.search_prefix("hubb", 100).unwrap();
+        assert!(result.len() > 3);
+        let result = indexer.search_prefix("hubbard comm", 100).unwrap();
+        assert_eq!(result.len(), 1);
+
+        let result = indexer.search("ext:sid").unwrap();
+        assert!(result.len() > 40);
+        let result = indexer.search("path:c64 hubbard").unwrap();
+        assert!(result.len() > 3);
+        let result = indexer.search("format:\"fasttracker 2\"").unwrap();
+        assert_eq!(result.len(), 1);
     }
 
     #[test]
@@ -806,6 +1737,164 @@ mod tests {
         assert!(songs.len() > 40);
     }
 
+    #[test]
+    fn cache_keeps_meta_data() {
+        let temp = tempfile::tempdir().unwrap();
+        let base = temp.path();
+        let dir = base.join("songs");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (mtime_secs, mtime_nanos) = dir_mtime(&dir).unwrap();
+
+        let mut song = FileInfo {
+            path: dir.join("song.sid"),
+            ..FileInfo::default()
+        };
+        song.meta_data
+            .insert("game".into(), Value::Text("Game".into()));
+        song.meta_data.insert("size".into(), Value::Number(1234.0));
+        let cache = DirCache {
+            mtime_secs,
+            mtime_nanos,
+            files: vec![CachedFileInfo::from_file_info(&song)],
+        };
+        save_cache(base, &dir, &cache);
+        let loaded = load_cache(base, &dir).unwrap();
+        let restored = loaded.files[0].clone().into_file_info();
+        assert_eq!(restored, song);
+
+        // Caches from older versions are ignored
+        let old = bincode::serialize(&cache).unwrap();
+        std::fs::write(cache_file_for_dir(base, &dir), old).unwrap();
+        assert!(load_cache(base, &dir).is_none());
+    }
+
+    #[test]
+    fn index_is_persisted() {
+        init_music();
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let songs: Vec<PathBuf> = WalkDir::new("music/C64")
+            .into_iter()
+            .flatten()
+            .filter(|e| e.path().is_file())
+            .take(5)
+            .map(|e| e.path().to_owned())
+            .collect();
+        {
+            let mut indexer = SongIndexer::open(dir).unwrap();
+            for song in &songs {
+                indexer.add_path(song).unwrap();
+            }
+            indexer.add_dir(Path::new("music/C64")).unwrap();
+            indexer.commit().unwrap();
+        }
+
+        let mut indexer = SongIndexer::open(dir).unwrap();
+        assert_eq!(indexer.count.load(Ordering::Relaxed), songs.len());
+        assert!(indexer.dir_unchanged(Path::new("music/C64")).unwrap());
+        assert!(indexer.contains(&songs[1]).unwrap());
+
+        // Removing the first song moves the last one into its place
+        indexer
+            .index_writer
+            .delete_term(Term::from_field_u64(indexer.index_field, 0));
+        indexer.commit().unwrap();
+        indexer.compact().unwrap();
+        assert_eq!(indexer.count.load(Ordering::Relaxed), songs.len() - 1);
+        assert_eq!(
+            indexer.get_song(0).unwrap().unwrap().path,
+            songs[songs.len() - 1]
+        );
+        assert_eq!(
+            indexer.song_at(songs.len() - 2).unwrap().path,
+            songs[songs.len() - 2]
+        );
+        assert!(indexer.song_at(songs.len() - 1).is_none());
+    }
+
+    #[test]
+    fn all_songs_can_be_browsed() {
+        let mut indexer = SongIndexer::new().unwrap();
+        let song = |i: usize| FileInfo {
+            path: PathBuf::from(format!("songs/{i}.mod")),
+            ..FileInfo::default()
+        };
+        for i in 0..250 {
+            indexer.add_song(&song(i)).unwrap();
+        }
+        indexer.commit().unwrap();
+        indexer.add_song(&song(250)).unwrap();
+        // Forget the pages kept while adding
+        indexer.pages.clear();
+        indexer.add_song(&song(251)).unwrap();
+
+        let songs = IndexedSongs {
+            indexer: Arc::new(Mutex::new(indexer)),
+        };
+        assert_eq!(songs.len(), 252);
+        assert_eq!(songs.get(0).path, song(0).path);
+        assert_eq!(songs.get(199).path, song(199).path);
+        assert_eq!(songs.get(230).path, song(230).path);
+        assert_eq!(songs.index_of(&song(123)), Some(123));
+        let range = songs.get_range(98, 102);
+        assert_eq!(range[3].path, song(101).path);
+        // Not committed, and the page was forgotten
+        assert_eq!(songs.get(250), FileInfo::default());
+
+        songs.indexer.lock().unwrap().commit().unwrap();
+        assert_eq!(songs.get(250).path, song(250).path);
+        assert_eq!(songs.get(251).path, song(251).path);
+
+        let mut indexer = songs.indexer.lock().unwrap();
+        indexer.clear_dir(Path::new("songs")).unwrap();
+        indexer.commit().unwrap();
+        drop(indexer);
+        assert_eq!(songs.get(0), FileInfo::default());
+    }
+
+    #[test]
+    fn changed_songs_keep_their_numbers() {
+        let mut indexer = SongIndexer::new().unwrap();
+        let song = |i: usize| FileInfo {
+            path: PathBuf::from(format!("songs/{i}.mod")),
+            ..FileInfo::default()
+        };
+        for i in 0..3 {
+            indexer.add_song(&song(i)).unwrap();
+        }
+        indexer.commit().unwrap();
+
+        assert_eq!(indexer.remove_song(&song(1).path).unwrap(), Some(1));
+        indexer.add_song_as(&song(1), 1).unwrap();
+        indexer.remove_song(&song(2).path).unwrap();
+        indexer.commit().unwrap();
+        assert_eq!(indexer.song_at(1).unwrap().path, song(1).path);
+        assert_eq!(indexer.get_song(2).unwrap(), None);
+        assert_eq!(indexer.count.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn archives_are_indexed() {
+        init_music();
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::copy("music.zip", dir.join("music.zip")).unwrap();
+        std::fs::copy("music.lha", dir.join("music.lha")).unwrap();
+        let indexer = RemoteSongIndexer::new().unwrap();
+        indexer.ignore_cache(true).unwrap();
+        indexer.add_path(dir).unwrap();
+        while indexer.working() {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        let entries = indexer.browse(dir).unwrap();
+        let songs = indexer.browse(&dir.join("music.lha")).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|e| e.file_type == FileType::Dir));
+        let song = archive::join(&dir.join("music.lha"), "mods/mdat.music");
+        assert!(songs.iter().any(|s| s.path == song));
+    }
+
     #[test]
     fn threaded_search_works() {
         init_music();
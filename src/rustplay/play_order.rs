@@ -0,0 +1,191 @@
+use rand::seq::SliceRandom;
+
+/// How the next song is picked when a song ends or `next_song` is called
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayMode {
+    #[default]
+    Normal,
+    RepeatOne,
+    RepeatAll,
+    Shuffle,
+    /// Shuffle over all indexed songs, not just the current playlist
+    ShuffleAll,
+}
+
+impl PlayMode {
+    const ALL: [PlayMode; 5] = [
+        PlayMode::Normal,
+        PlayMode::RepeatOne,
+        PlayMode::RepeatAll,
+        PlayMode::Shuffle,
+        PlayMode::ShuffleAll,
+    ];
+
+    /// Name used from Lua
+    pub fn name(self) -> &'static str {
+        match self {
+            PlayMode::Normal => "normal",
+            PlayMode::RepeatOne => "repeat_one",
+            PlayMode::RepeatAll => "repeat_all",
+            PlayMode::Shuffle => "shuffle",
+            PlayMode::ShuffleAll => "shuffle_all",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PlayMode> {
+        PlayMode::ALL.into_iter().find(|m| m.name() == name)
+    }
+
+    /// Text for the `$play_mode` template variable
+    pub fn label(self) -> &'static str {
+        match self {
+            PlayMode::Normal => "",
+            PlayMode::RepeatOne => "REPEAT ONE",
+            PlayMode::RepeatAll => "REPEAT ALL",
+            PlayMode::Shuffle => "SHUFFLE",
+            PlayMode::ShuffleAll => "SHUFFLE ALL",
+        }
+    }
+
+    /// The mode after this one, for cycling through all modes
+    pub fn next(self) -> PlayMode {
+        let i = PlayMode::ALL.iter().position(|m| *m == self).unwrap_or(0);
+        PlayMode::ALL[(i + 1) % PlayMode::ALL.len()]
+    }
+
+    pub fn is_shuffle(self) -> bool {
+        matches!(self, PlayMode::Shuffle | PlayMode::ShuffleAll)
+    }
+}
+
+/// Decides which song in a playlist to play next.
+///
+/// In shuffle modes all songs are played once in random order before
+/// the playlist is reshuffled.
+#[derive(Default)]
+pub struct PlayOrder {
+    pub mode: PlayMode,
+    order: Vec<usize>,
+    pos: usize,
+}
+
+impl PlayOrder {
+    /// Set up a new random order for `len` songs, starting with `first`
+    pub fn shuffle(&mut self, len: usize, first: Option<usize>) {
+        let mut order: Vec<usize> = (0..len).filter(|i| Some(*i) != first).collect();
+        order.shuffle(&mut rand::thread_rng());
+        if let Some(first) = first.filter(|f| *f < len) {
+            order.insert(0, first);
+        }
+        self.order = order;
+        self.pos = 0;
+    }
+
+    /// The song at the current position of the shuffled order
+    pub fn current(&self) -> Option<usize> {
+        self.order.get(self.pos).copied()
+    }
+
+    /// Must be called when the playlist changes
+    pub fn reset(&mut self, len: usize, current: usize) {
+        if self.mode.is_shuffle() {
+            self.shuffle(len, Some(current));
+        } else {
+            self.order.clear();
+        }
+    }
+
+    /// The song that `next()` will return, if it is known
+    pub fn peek_next(&self, current: usize, len: usize) -> Option<usize> {
+        if len == 0 {
+            return None;
+        }
+        match self.mode {
+            PlayMode::Normal | PlayMode::RepeatOne => (current + 1 < len).then_some(current + 1),
+            PlayMode::RepeatAll => Some((current + 1) % len),
+            PlayMode::Shuffle | PlayMode::ShuffleAll => self.order.get(self.pos + 1).copied(),
+        }
+    }
+
+    /// Step to the song after `current` in a playlist with `len` songs
+    pub fn next(&mut self, current: usize, len: usize) -> Option<usize> {
+        if !self.mode.is_shuffle() {
+            return self.peek_next(current, len);
+        }
+        if len == 0 {
+            return None;
+        }
+        if self.order.len() != len {
+            // Playlist has grown (still indexing), start over from here
+            self.shuffle(len, Some(current));
+        }
+        if self.pos + 1 < self.order.len() {
+            self.pos += 1;
+        } else {
+            self.shuffle(len, None);
+        }
+        self.order.get(self.pos).copied()
+    }
+
+    /// Step to the song before `current` in a playlist with `len` songs
+    pub fn prev(&mut self, current: usize, len: usize) -> Option<usize> {
+        if len == 0 {
+            return None;
+        }
+        match self.mode {
+            PlayMode::Normal | PlayMode::RepeatOne => current.checked_sub(1),
+            PlayMode::RepeatAll => Some(current.checked_sub(1).unwrap_or(len - 1)),
+            PlayMode::Shuffle | PlayMode::ShuffleAll => {
+                self.pos = self.pos.checked_sub(1)?;
+                self.order.get(self.pos).copied()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{PlayMode, PlayOrder};
+
+    #[test]
+    fn shuffle_plays_all_songs_once() {
+        let mut order = PlayOrder {
+            mode: PlayMode::Shuffle,
+            ..PlayOrder::default()
+        };
+        order.reset(20, 5);
+        let mut played = HashSet::from([5]);
+        let mut current = 5;
+        for _ in 0..19 {
+            current = order.next(current, 20).unwrap();
+            assert!(played.insert(current));
+        }
+        assert_eq!(played.len(), 20);
+        assert!(order.prev(current, 20).is_some());
+    }
+
+    #[test]
+    fn repeat_all_wraps() {
+        let mut order = PlayOrder {
+            mode: PlayMode::RepeatAll,
+            ..PlayOrder::default()
+        };
+        assert_eq!(order.next(2, 3), Some(0));
+        assert_eq!(order.prev(0, 3), Some(2));
+        order.mode = PlayMode::Normal;
+        assert_eq!(order.next(2, 3), None);
+    }
+
+    #[test]
+    fn modes_cycle() {
+        let mut mode = PlayMode::Normal;
+        for _ in 0..5 {
+            assert_eq!(PlayMode::from_name(mode.name()), Some(mode));
+            mode = mode.next();
+        }
+        assert_eq!(mode, PlayMode::Normal);
+    }
+}
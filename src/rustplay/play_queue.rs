@@ -0,0 +1,119 @@
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use super::song::{FileInfo, SongArray, SongCollection};
+
+/// Songs explicitly lined up by the user. These are played before
+/// continuing with the current playlist.
+#[derive(Default)]
+pub struct PlayQueue {
+    songs: VecDeque<FileInfo>,
+}
+
+impl PlayQueue {
+    /// Add a song to the end of the queue
+    pub fn push(&mut self, song: FileInfo) {
+        self.songs.push_back(song);
+    }
+
+    /// Add a song so it will be played next
+    pub fn push_next(&mut self, song: FileInfo) {
+        self.songs.push_front(song);
+    }
+
+    /// Take the next song to play
+    pub fn pop(&mut self) -> Option<FileInfo> {
+        self.songs.pop_front()
+    }
+
+    pub fn peek(&self) -> Option<&FileInfo> {
+        self.songs.front()
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<FileInfo> {
+        self.songs.remove(index)
+    }
+
+    /// Move the song at `index` `delta` steps towards the end of the queue.
+    /// Returns the new position of the song.
+    pub fn move_song(&mut self, index: usize, delta: i32) -> Option<usize> {
+        if index >= self.songs.len() {
+            return None;
+        }
+        let target = index
+            .saturating_add_signed(delta as isize)
+            .min(self.songs.len() - 1);
+        let song = self.songs.remove(index)?;
+        self.songs.insert(target, song);
+        Some(target)
+    }
+
+    pub fn clear(&mut self) {
+        self.songs.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.songs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.songs.is_empty()
+    }
+
+    pub fn songs(&self) -> Vec<FileInfo> {
+        self.songs.iter().cloned().collect()
+    }
+
+    /// A snapshot of the queue that can be shown in a menu
+    pub fn collection(&self) -> Rc<dyn SongCollection> {
+        Rc::new(SongArray {
+            songs: self.songs(),
+        })
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::PlayQueue;
+    use crate::rustplay::song::FileInfo;
+
+    fn song(name: &str) -> FileInfo {
+        FileInfo {
+            path: PathBuf::from(name),
+            ..FileInfo::default()
+        }
+    }
+
+    fn names(queue: &PlayQueue) -> Vec<String> {
+        queue
+            .songs()
+            .iter()
+            .map(|s| s.path.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn queue_order() {
+        let mut queue = PlayQueue::default();
+        queue.push(song("b"));
+        queue.push(song("c"));
+        queue.push_next(song("a"));
+        assert_eq!(names(&queue), ["a", "b", "c"]);
+
+        assert_eq!(queue.move_song(0, 1), Some(1));
+        assert_eq!(names(&queue), ["b", "a", "c"]);
+        assert_eq!(queue.move_song(2, -5), Some(0));
+        assert_eq!(names(&queue), ["c", "b", "a"]);
+        assert_eq!(queue.move_song(0, 10), Some(2));
+        assert_eq!(names(&queue), ["b", "a", "c"]);
+
+        assert_eq!(queue.remove(1).unwrap().path, PathBuf::from("a"));
+        assert_eq!(queue.pop().unwrap().path, PathBuf::from("b"));
+        assert_eq!(queue.len(), 1);
+        queue.clear();
+        assert!(queue.pop().is_none());
+    }
+}
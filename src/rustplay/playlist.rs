@@ -0,0 +1,324 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use anyhow::{Result, bail};
+use regex::Regex;
+
+use super::song::FileInfo;
+use crate::value::Value;
+
+const EXTENSIONS: [&str; 4] = ["m3u", "m3u8", "pls", "xspf"];
+
+static XSPF_TRACK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<track>(.*?)</track>").unwrap());
+static XSPF_FIELD: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<(location|title|creator|duration)>(.*?)</").unwrap());
+
+/// A song in a playlist, with what the playlist says about it
+#[derive(Default, Debug, PartialEq)]
+struct Entry {
+    path: PathBuf,
+    title: Option<String>,
+    composer: Option<String>,
+    seconds: Option<f64>,
+    /// Subtune number, 1 = first
+    subtune: Option<u32>,
+}
+
+pub fn is_playlist(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| EXTENSIONS.contains(&e.to_string_lossy().to_lowercase().as_str()))
+}
+
+/// Read the songs in the playlist `file`. Songs are identified like when
+/// indexing, but titles, lengths and subtunes in the playlist take
+/// precedence.
+pub fn load(file: &Path, identify: impl Fn(&Path) -> FileInfo) -> Result<Vec<FileInfo>> {
+    let text = fs::read_to_string(file)?;
+    let ext = file
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let dir = file.parent().unwrap_or(Path::new(""));
+    let entries = match ext.as_str() {
+        "m3u" | "m3u8" => parse_m3u(&text, dir),
+        "pls" => parse_pls(&text, dir),
+        "xspf" => parse_xspf(&text, dir),
+        _ => bail!("Unknown playlist format {file:?}"),
+    };
+    Ok(entries
+        .into_iter()
+        .map(|entry| {
+            let path = dir.join(&entry.path);
+            let mut song = identify(&path);
+            song.path = path;
+            if let Some(title) = entry.title {
+                song.meta_data.insert("title".into(), Value::Text(title));
+            }
+            if let Some(composer) = entry.composer {
+                song.meta_data
+                    .insert("composer".into(), Value::Text(composer));
+            }
+            if let Some(seconds) = entry.seconds {
+                song.meta_data
+                    .insert("length".into(), Value::Number(seconds));
+            }
+            if let Some(subtune) = entry.subtune {
+                song.meta_data
+                    .insert("subtune".into(), Value::Number(f64::from(subtune)));
+            }
+            song
+        })
+        .collect())
+}
+
+/// Write `songs` to `file` as an extended M3U playlist
+pub fn save_m3u(file: &Path, songs: &[FileInfo]) -> Result<()> {
+    let mut text = String::from("#EXTM3U\n");
+    for song in songs {
+        let seconds = song.length().map_or(-1, |s| s.round() as i64);
+        let title = match song.get("composer") {
+            Value::Text(composer) if !composer.is_empty() => {
+                format!("{composer} - {}", song.get_title())
+            }
+            _ => song.get_title().to_owned(),
+        };
+        writeln!(text, "#EXTINF:{seconds},{title}")?;
+        let path = song.path.to_string_lossy();
+        match song.get("subtune") {
+            Value::Number(n) => writeln!(text, "{path}#{n}")?,
+            _ => writeln!(text, "{path}")?,
+        }
+    }
+    fs::write(file, text)?;
+    Ok(())
+}
+
+/// Split off a subtune number, as in `Commando.sid#3`, unless a file in
+/// `dir` is named like that
+fn split_subtune(location: &str, dir: &Path) -> (PathBuf, Option<u32>) {
+    if let Some((path, subtune)) = location.rsplit_once('#')
+        && let Ok(subtune) = subtune.parse()
+        && !dir.join(location).exists()
+    {
+        return (path.into(), Some(subtune));
+    }
+    (location.into(), None)
+}
+
+fn entry(location: &str, dir: &Path) -> Entry {
+    let location = location.strip_prefix("file://").unwrap_or(location);
+    let (path, subtune) = split_subtune(location, dir);
+    Entry {
+        path,
+        subtune,
+        ..Entry::default()
+    }
+}
+
+fn is_remote(location: &str) -> bool {
+    location.contains("://") && !location.starts_with("file://")
+}
+
+fn seconds(text: &str) -> Option<f64> {
+    text.trim().parse::<f64>().ok().filter(|s| *s > 0.0)
+}
+
+fn parse_m3u(text: &str, dir: &Path) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut info: Option<(Option<f64>, String)> = None;
+    for line in text.lines().map(str::trim) {
+        if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            let (secs, title) = extinf.split_once(',').unwrap_or((extinf, ""));
+            info = Some((seconds(secs), title.trim().to_owned()));
+        } else if !line.is_empty() && !line.starts_with('#') && !is_remote(line) {
+            let mut entry = entry(line, dir);
+            if let Some((secs, title)) = info.take() {
+                entry.seconds = secs;
+                entry.title = (!title.is_empty()).then_some(title);
+            }
+            entries.push(entry);
+        }
+    }
+    entries
+}
+
+fn parse_pls(text: &str, dir: &Path) -> Vec<Entry> {
+    let mut entries: BTreeMap<u32, Entry> = BTreeMap::new();
+    for line in text.lines().map(str::trim) {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let split = key.find(|c: char| c.is_ascii_digit()).unwrap_or(key.len());
+        let (name, number) = key.split_at(split);
+        let Ok(number) = number.parse() else {
+            continue;
+        };
+        let entry = entries.entry(number).or_default();
+        match name.to_lowercase().as_str() {
+            "file" if !is_remote(value) => {
+                let Entry { path, subtune, .. } = self::entry(value, dir);
+                entry.path = path;
+                entry.subtune = subtune;
+            }
+            "title" => entry.title = Some(value.to_owned()),
+            "length" => entry.seconds = seconds(value),
+            _ => {}
+        }
+    }
+    entries
+        .into_values()
+        .filter(|e| !e.path.as_os_str().is_empty())
+        .collect()
+}
+
+/// Decode `%xx` escapes and XML entities
+fn unescape(text: &str) -> String {
+    let text = text
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(hex) = text.get(i + 1..i + 3)
+            && let Ok(b) = u8::from_str_radix(hex, 16)
+        {
+            out.push(b);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn parse_xspf(text: &str, dir: &Path) -> Vec<Entry> {
+    XSPF_TRACK
+        .captures_iter(text)
+        .filter_map(|track| {
+            let mut entry = None;
+            let mut fields = Entry::default();
+            for field in XSPF_FIELD.captures_iter(&track[1]) {
+                let value = field[2].trim();
+                match &field[1] {
+                    "location" if !is_remote(value) => {
+                        entry = Some(self::entry(&unescape(value), dir))
+                    }
+                    "title" => fields.title = Some(unescape(value)),
+                    "creator" => fields.composer = Some(unescape(value)),
+                    // In milliseconds
+                    "duration" => fields.seconds = seconds(value).map(|ms| ms / 1000.0),
+                    _ => {}
+                }
+            }
+            entry.map(|e| Entry {
+                path: e.path,
+                subtune: e.subtune,
+                ..fields
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{Entry, load, parse_m3u, parse_pls, parse_xspf, save_m3u};
+    use crate::rustplay::song::FileInfo;
+    use crate::value::Value;
+
+    #[test]
+    fn playlists_are_parsed() {
+        let m3u = parse_m3u(
+            "#EXTM3U\n#EXTINF:123,Rob Hubbard - Commando\nC64/Commando.sid#3\nhttp://radio/x\n\nmusic.mod\n",
+            Path::new(""),
+        );
+        assert_eq!(
+            m3u,
+            [
+                Entry {
+                    path: "C64/Commando.sid".into(),
+                    title: Some("Rob Hubbard - Commando".into()),
+                    seconds: Some(123.0),
+                    subtune: Some(3),
+                    ..Entry::default()
+                },
+                Entry {
+                    path: "music.mod".into(),
+                    ..Entry::default()
+                }
+            ]
+        );
+
+        let pls = parse_pls(
+            "[playlist]\nFile2=b.mod\nFile1=/music/a.sid#2\nTitle1=A\nLength1=-1\nNumberOfEntries=2\n",
+            Path::new(""),
+        );
+        assert_eq!(pls[0].path, PathBuf::from("/music/a.sid"));
+        assert_eq!(pls[0].subtune, Some(2));
+        assert_eq!(pls[0].title.as_deref(), Some("A"));
+        assert_eq!(pls[0].seconds, None);
+        assert_eq!(pls[1].path, PathBuf::from("b.mod"));
+
+        let xspf = parse_xspf(
+            r#"<playlist version="1"><trackList>
+            <track><location>file:///music/Hymn%20to%20Yezz.mod</location>
+            <title>Hymn &amp; Yezz</title><creator>Jester</creator><duration>90500</duration></track>
+            </trackList></playlist>"#,
+            Path::new(""),
+        );
+        assert_eq!(xspf[0].path, PathBuf::from("/music/Hymn to Yezz.mod"));
+        assert_eq!(xspf[0].title.as_deref(), Some("Hymn & Yezz"));
+        assert_eq!(xspf[0].composer.as_deref(), Some("Jester"));
+        assert_eq!(xspf[0].seconds, Some(90.5));
+
+        // A file named like a subtune, next to the playlist
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("hit#1.mod"), b"").unwrap();
+        let m3u = parse_m3u("hit#1.mod\nhit.sid#1\n", dir.path());
+        assert_eq!(m3u[0].path, PathBuf::from("hit#1.mod"));
+        assert_eq!(m3u[0].subtune, None);
+        assert_eq!(m3u[1].subtune, Some(1));
+    }
+
+    #[test]
+    fn m3u_is_saved() {
+        let mut song = FileInfo {
+            path: "/music/Commando.sid".into(),
+            ..FileInfo::default()
+        };
+        song.meta_data.insert("title".into(), "Commando".into());
+        song.meta_data
+            .insert("composer".into(), "Rob Hubbard".into());
+        song.meta_data.insert("length".into(), Value::Number(61.6));
+        song.meta_data.insert("subtune".into(), Value::Number(2.0));
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("songs.m3u");
+        save_m3u(&file, &[song]).unwrap();
+        let text = std::fs::read_to_string(&file).unwrap();
+        let songs = load(&file, |p| FileInfo {
+            path: p.to_owned(),
+            ..FileInfo::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            text,
+            "#EXTM3U\n#EXTINF:62,Rob Hubbard - Commando\n/music/Commando.sid#2\n"
+        );
+        assert_eq!(songs[0].path, Path::new("/music/Commando.sid"));
+        assert_eq!(songs[0].get("subtune"), &Value::Number(2.0));
+        assert_eq!(songs[0].length(), Some(62.0));
+    }
+}
@@ -0,0 +1,362 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use super::playlist;
+use super::song::FileInfo;
+use crate::log;
+use crate::value::Value;
+
+/// Meta data kept in the playlist, so songs show up the same way even if
+/// they can not be identified
+const SAVED_META: [&str; 3] = ["title", "composer", "length"];
+
+/// A song in a saved playlist
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct PlaylistEntry {
+    /// Where the song is, not a copy of it
+    pub path: PathBuf,
+    /// Subtune number, 1 = first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtune: Option<u32>,
+    /// Meta data that replaces what is found in the song
+    #[serde(default, skip_serializing_if = "toml::Table::is_empty")]
+    pub meta: toml::Table,
+}
+
+impl PlaylistEntry {
+    pub fn from_song(song: &FileInfo) -> Self {
+        let mut meta = toml::Table::new();
+        for key in SAVED_META {
+            if let Some(value) = to_toml(song.get(key)) {
+                meta.insert(key.into(), value);
+            }
+        }
+        let subtune = match song.get("subtune") {
+            Value::Number(n) if *n >= 1.0 => Some(*n as u32),
+            _ => None,
+        };
+        Self {
+            path: song.path.clone(),
+            subtune,
+            meta,
+        }
+    }
+
+    /// The song as found by `identify`, with the meta data from the
+    /// playlist on top
+    pub fn to_song(&self, identify: impl Fn(&Path) -> FileInfo) -> FileInfo {
+        let mut song = identify(&self.path);
+        song.path = self.path.clone();
+        for (key, value) in &self.meta {
+            if let Some(value) = from_toml(value) {
+                song.meta_data.insert(key.clone(), value);
+            }
+        }
+        if let Some(subtune) = self.subtune {
+            song.meta_data
+                .insert("subtune".into(), Value::Number(f64::from(subtune)));
+        }
+        song
+    }
+}
+
+fn to_toml(value: &Value) -> Option<toml::Value> {
+    match value {
+        Value::Text(s) if !s.is_empty() => Some(toml::Value::String(s.clone())),
+        Value::Number(n) if *n == (*n as i64) as f64 => Some(toml::Value::Integer(*n as i64)),
+        Value::Number(n) => Some(toml::Value::Float(*n)),
+        _ => None,
+    }
+}
+
+fn from_toml(value: &toml::Value) -> Option<Value> {
+    match value {
+        toml::Value::String(s) => Some(Value::Text(s.clone())),
+        toml::Value::Float(n) => Some(Value::Number(*n)),
+        toml::Value::Integer(n) => Some(Value::Number(*n as f64)),
+        _ => None,
+    }
+}
+
+/// Just the path, for when the song will not be shown
+fn path_only(path: &Path) -> FileInfo {
+    FileInfo {
+        path: path.to_owned(),
+        ..FileInfo::default()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct PlaylistFile {
+    songs: Vec<PlaylistEntry>,
+}
+
+/// A named list of songs, saved as a TOML or M3U file
+#[derive(Debug, Clone)]
+pub struct Playlist {
+    pub name: String,
+    file: PathBuf,
+    pub entries: Vec<PlaylistEntry>,
+}
+
+fn is_toml(file: &Path) -> bool {
+    file.extension().is_some_and(|e| e == "toml")
+}
+
+/// Files in the playlist dir that are playlists
+fn is_manifest(file: &Path) -> bool {
+    file.extension()
+        .is_some_and(|e| e == "toml" || e == "m3u" || e == "m3u8")
+}
+
+impl Playlist {
+    fn load(file: &Path) -> Result<Playlist> {
+        let name = file
+            .file_stem()
+            .context("Playlist file has no name")?
+            .to_string_lossy()
+            .into_owned();
+        let entries = if is_toml(file) {
+            toml::from_str::<PlaylistFile>(&fs::read_to_string(file)?)?.songs
+        } else {
+            playlist::load(file, path_only)?
+                .iter()
+                .map(PlaylistEntry::from_song)
+                .collect()
+        };
+        Ok(Playlist {
+            name,
+            file: file.to_owned(),
+            entries,
+        })
+    }
+
+    /// Write the playlist in the format it was read in
+    fn save(&self) -> Result<()> {
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if is_toml(&self.file) {
+            let playlist = PlaylistFile {
+                songs: self.entries.clone(),
+            };
+            fs::write(&self.file, toml::to_string(&playlist)?)?;
+        } else {
+            let songs: Vec<FileInfo> = self.entries.iter().map(|e| e.to_song(path_only)).collect();
+            playlist::save_m3u(&self.file, &songs)?;
+        }
+        Ok(())
+    }
+
+    /// The songs in the playlist, identified with `identify`
+    pub fn songs(&self, identify: impl Fn(&Path) -> FileInfo) -> Vec<FileInfo> {
+        self.entries.iter().map(|e| e.to_song(&identify)).collect()
+    }
+}
+
+/// All playlists in the playlist dir, ordered by name
+pub struct Playlists {
+    dir: PathBuf,
+    lists: Vec<Playlist>,
+}
+
+impl Playlists {
+    /// Load all playlists in `dir`. Playlists that can not be read are
+    /// skipped.
+    pub fn load(dir: PathBuf) -> Self {
+        let lists = fs::read_dir(&dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| is_manifest(path))
+            .filter_map(|path| match Playlist::load(&path) {
+                Ok(list) => Some(list),
+                Err(e) => {
+                    log!("Could not read playlist {path:?}: {e}");
+                    None
+                }
+            })
+            .collect();
+        let mut playlists = Self { dir, lists };
+        playlists.sort();
+        playlists
+    }
+
+    fn sort(&mut self) {
+        self.lists.sort_by_key(|l| l.name.to_lowercase());
+    }
+
+    pub fn lists(&self) -> &[Playlist] {
+        &self.lists
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Playlist> {
+        self.lists.iter().find(|l| l.name == name)
+    }
+
+    fn get_mut(&mut self, name: &str) -> Result<&mut Playlist> {
+        self.lists
+            .iter_mut()
+            .find(|l| l.name == name)
+            .with_context(|| format!("No playlist named '{name}'"))
+    }
+
+    /// Create an empty playlist, saved as TOML
+    pub fn create(&mut self, name: &str) -> Result<()> {
+        if name.trim().is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            bail!("Illegal playlist name '{name}'");
+        }
+        if self.get(name).is_some() {
+            bail!("Playlist '{name}' already exists");
+        }
+        let list = Playlist {
+            name: name.to_owned(),
+            file: self.dir.join(format!("{name}.toml")),
+            entries: Vec::new(),
+        };
+        list.save()?;
+        self.lists.push(list);
+        self.sort();
+        Ok(())
+    }
+
+    /// Add `song` to the playlist `name`, creating the playlist if needed.
+    /// Returns false if the song was already in it.
+    pub fn add(&mut self, name: &str, song: &FileInfo) -> Result<bool> {
+        if self.get(name).is_none() {
+            self.create(name)?;
+        }
+        let list = self.get_mut(name)?;
+        let entry = PlaylistEntry::from_song(song);
+        if list
+            .entries
+            .iter()
+            .any(|e| e.path == entry.path && e.subtune == entry.subtune)
+        {
+            return Ok(false);
+        }
+        list.entries.push(entry);
+        list.save()?;
+        Ok(true)
+    }
+
+    /// Remove song number `index` from the playlist `name`
+    pub fn remove(&mut self, name: &str, index: usize) -> Result<()> {
+        let list = self.get_mut(name)?;
+        if index < list.entries.len() {
+            list.entries.remove(index);
+            list.save()?;
+        }
+        Ok(())
+    }
+
+    /// Turn the songs copied to the old favorites dir into the playlist
+    /// `name`. Meta data from the `.meta` files is kept in the playlist.
+    /// Returns the number of songs imported.
+    pub fn import_favorites(&mut self, favorites_dir: &Path, name: &str) -> Result<usize> {
+        let mut files: Vec<PathBuf> = fs::read_dir(favorites_dir)?
+            .flatten()
+            .map(|entry| entry.path())
+            // Sample files were copied along with the songs that need them
+            .filter(|path| {
+                path.is_file() && path.extension().is_none_or(|e| e != "meta" && e != "smpl")
+            })
+            .collect();
+        files.sort();
+        let entries: Vec<PlaylistEntry> = files
+            .into_iter()
+            .map(|path| {
+                let mut meta_file = path.clone().into_os_string();
+                meta_file.push(".meta");
+                let meta = fs::read_to_string(meta_file)
+                    .ok()
+                    .and_then(|text| text.parse::<toml::Table>().ok())
+                    .unwrap_or_default();
+                PlaylistEntry {
+                    path,
+                    subtune: None,
+                    meta,
+                }
+            })
+            .collect();
+        let count = entries.len();
+        if self.get(name).is_none() {
+            self.create(name)?;
+        }
+        let list = self.get_mut(name)?;
+        list.entries.extend(entries);
+        list.save()?;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::Playlists;
+    use crate::rustplay::song::FileInfo;
+    use crate::value::Value;
+
+    #[test]
+    fn playlists_are_saved() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let favorites = dir.join("favorites");
+        fs::create_dir_all(&favorites).unwrap();
+        fs::write(favorites.join("song.mod"), "").unwrap();
+        fs::write(favorites.join("song.mod.meta"), "title = \"Old Title\"\n").unwrap();
+        fs::write(favorites.join("song.smpl"), "").unwrap();
+        fs::write(
+            dir.join("Party.m3u"),
+            "#EXTM3U\n#EXTINF:90,Intro\n/music/intro.sid#2\n",
+        )
+        .unwrap();
+
+        let mut playlists = Playlists::load(dir.to_owned());
+        assert_eq!(
+            playlists.import_favorites(&favorites, "Favorites").unwrap(),
+            1
+        );
+        let mut song = FileInfo {
+            path: "/music/Commando.sid".into(),
+            ..FileInfo::default()
+        };
+        song.meta_data.insert("title".into(), "Commando".into());
+        song.meta_data
+            .insert("composer".into(), "Rob Hubbard".into());
+        song.meta_data.insert("subtune".into(), Value::Number(3.0));
+        assert!(playlists.add("Favorites", &song).unwrap());
+        assert!(!playlists.add("Favorites", &song).unwrap());
+        assert!(playlists.add("Party", &song).unwrap());
+        assert!(playlists.create("Party").is_err());
+        assert!(playlists.create("../x").is_err());
+
+        let loaded = Playlists::load(dir.to_owned());
+        let names: Vec<_> = loaded.lists().iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, ["Favorites", "Party"]);
+        let identify = |p: &Path| FileInfo {
+            path: p.to_owned(),
+            ..FileInfo::default()
+        };
+        let favorites = loaded.get("Favorites").unwrap().songs(identify);
+        assert_eq!(favorites[0].get_title(), "Old Title");
+        assert_eq!(favorites[1].get_title(), "Commando");
+        assert_eq!(favorites[1].get("subtune"), &Value::Number(3.0));
+        assert_eq!(
+            favorites[1].full_song_name(),
+            "Commando / Rob Hubbard #3 [sid]"
+        );
+        let party = loaded.get("Party").unwrap().songs(identify);
+        assert_eq!(party[0].path, Path::new("/music/intro.sid"));
+        assert_eq!(party[0].get("subtune"), &Value::Number(2.0));
+        assert_eq!(party[0].length(), Some(90.0));
+        assert_eq!(party[1].path, Path::new("/music/Commando.sid"));
+    }
+}
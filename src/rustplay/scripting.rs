@@ -26,7 +26,9 @@ pub struct Override {
 }
 
 use crate::Settings;
+use crate::rustplay::play_order::PlayMode;
 use crate::rustplay::song::FileInfo;
+use crate::rustplay::sort_order::SortOrder;
 use crate::rustplay::state::InputMode;
 use crate::{RustPlay, log, value::Value};
 
@@ -56,6 +58,14 @@ impl UserData for RustPlay {
             this.set_song(song);
             Ok(())
         });
+        methods.add_method_mut("seek", |_, this: &mut RustPlay, (secs,): (f64,)| {
+            this.seek(secs);
+            Ok(())
+        });
+        methods.add_method_mut("seek_to", |_, this: &mut RustPlay, (secs,): (f64,)| {
+            this.seek_to(secs);
+            Ok(())
+        });
         methods.add_method_mut("goto_parent", |_, this: &mut RustPlay, ()| {
             this.goto_parent().map_err(mlua::Error::external)
         });
@@ -84,10 +94,96 @@ impl UserData for RustPlay {
         methods.add_method_mut("enter_or_play_selected", |_, this: &mut RustPlay, ()| {
             this.enter_or_play_selected().map_err(mlua::Error::external)
         });
+        methods.add_method_mut("show_history", |_, this: &mut RustPlay, ()| {
+            this.show_history();
+            Ok(())
+        });
+        methods.add_method_mut("show_queue", |_, this: &mut RustPlay, ()| {
+            this.show_queue();
+            Ok(())
+        });
+        methods.add_method_mut(
+            "enqueue",
+            |_, this: &mut RustPlay, (song,): (LuaUserDataRef<FileInfo>,)| {
+                this.enqueue(song.clone());
+                Ok(())
+            },
+        );
+        methods.add_method_mut(
+            "enqueue_next",
+            |_, this: &mut RustPlay, (song,): (LuaUserDataRef<FileInfo>,)| {
+                this.enqueue_next(song.clone());
+                Ok(())
+            },
+        );
+        methods.add_method_mut("dequeue_selected", |_, this: &mut RustPlay, ()| {
+            this.dequeue_selected();
+            Ok(())
+        });
+        methods.add_method_mut("move_queued", |_, this: &mut RustPlay, (delta,): (i32,)| {
+            this.move_queued(delta);
+            Ok(())
+        });
+        methods.add_method_mut("clear_queue", |_, this: &mut RustPlay, ()| {
+            this.clear_queue();
+            Ok(())
+        });
+        methods.add_method("get_queue", |_, this: &RustPlay, ()| Ok(this.get_queue()));
+        methods.add_method_mut(
+            "export_m3u",
+            |_, this: &mut RustPlay, (file, what): (String, Option<String>)| {
+                this.export_m3u(&file, what.as_deref().unwrap_or("playlist"))
+                    .map_err(mlua::Error::external)
+            },
+        );
         methods.add_method_mut("play_pause", |_, this: &mut RustPlay, ()| {
             this.play_pause();
             Ok(())
         });
+        methods.add_method_mut("volume_up", |_, this: &mut RustPlay, ()| {
+            this.volume_up();
+            Ok(())
+        });
+        methods.add_method_mut("volume_down", |_, this: &mut RustPlay, ()| {
+            this.volume_down();
+            Ok(())
+        });
+        methods.add_method_mut("mute", |_, this: &mut RustPlay, ()| {
+            this.mute();
+            Ok(())
+        });
+        methods.add_method_mut("cycle_play_mode", |_, this: &mut RustPlay, ()| {
+            this.cycle_play_mode();
+            Ok(())
+        });
+        methods.add_method_mut(
+            "set_play_mode",
+            |_, this: &mut RustPlay, (name,): (String,)| {
+                let mode = PlayMode::from_name(&name)
+                    .ok_or_else(|| mlua::Error::external(format!("Unknown play mode '{name}'")))?;
+                this.set_play_mode(mode);
+                Ok(())
+            },
+        );
+        methods.add_method("get_play_mode", |_, this: &RustPlay, ()| {
+            Ok(this.play_mode().name())
+        });
+        methods.add_method_mut("cycle_sort_order", |_, this: &mut RustPlay, ()| {
+            this.cycle_sort_order();
+            Ok(())
+        });
+        methods.add_method_mut(
+            "set_sort_order",
+            |_, this: &mut RustPlay, (name,): (String,)| {
+                let sort = SortOrder::from_name(&name)
+                    .ok_or_else(|| mlua::Error::external(format!("Unknown sort order '{name}'")))?;
+                this.set_sort_order(sort);
+                Ok(())
+            },
+        );
+        methods.add_method("get_sort_order", |_, this: &RustPlay, ()| {
+            Ok(this.sort_order().name())
+        });
         methods.add_method_mut(
             "add_favorite",
             |_, this: &mut RustPlay, (song,): (LuaUserDataRef<FileInfo>,)| {
@@ -97,6 +193,19 @@ impl UserData for RustPlay {
                 Ok(())
             },
         );
+        methods.add_method_mut("show_playlists", |_, this: &mut RustPlay, ()| {
+            this.show_playlists();
+            Ok(())
+        });
+        methods.add_method_mut(
+            "new_playlist",
+            |_, this: &mut RustPlay, (name,): (String,)| {
+                this.new_playlist(&name).map_err(mlua::Error::external)
+            },
+        );
+        methods.add_method_mut("remove_from_playlist", |_, this: &mut RustPlay, ()| {
+            this.remove_from_playlist().map_err(mlua::Error::external)
+        });
         methods.add_method_mut("quit", |_, this: &mut RustPlay, ()| {
             this.quit();
             Ok(())
@@ -114,6 +223,9 @@ impl UserData for RustPlay {
                 InputMode::DirScreen => "d",
                 InputMode::SearchScreen => "s",
                 InputMode::FavScreen => "f",
+                InputMode::QueueScreen => "q",
+                InputMode::HistoryScreen => "h",
+                InputMode::PlaylistsScreen => "p",
                 InputMode::ResultScreen => "r",
             })
         });
@@ -158,13 +270,21 @@ impl Scripting {
 
         let prelude = r#"
 function play_pause() rust_play:play_pause() end
+function volume_up() rust_play:volume_up() end
+function volume_down() rust_play:volume_down() end
+function mute() rust_play:mute() end
 function next_song() rust_play:next_song() end
 function prev_song() rust_play:prev_song() end
 function next_subtune() rust_play:next_subtune() end
 function prev_subtune() rust_play:prev_subtune() end
 function sub_song(n) rust_play:set_song(n) end
+function seek(secs) rust_play:seek(secs) end
+function seek_to(secs) rust_play:seek_to(secs) end
 function goto_parent() rust_play:goto_parent() end
 function show_favorites() rust_play:show_favorites() end
+function show_playlists() rust_play:show_playlists() end
+function new_playlist(name) rust_play:new_playlist(name) end
+function remove_from_playlist() rust_play:remove_from_playlist() end
 function show_directory() rust_play:show_directory() end
 function show_main() rust_play:show_main() end
 function focus_search() rust_play:focus_search_edit() end
@@ -175,6 +295,21 @@ function add_favorite(song) rust_play:add_favorite(song) end
 function add_char(c) rust_play:add_char(c) end
 function show_current() rust_play:show_current() end
 function enter_or_play_selected() rust_play:enter_or_play_selected() end
+function cycle_play_mode() rust_play:cycle_play_mode() end
+function show_queue() rust_play:show_queue() end
+function show_history() rust_play:show_history() end
+function enqueue(song) rust_play:enqueue(song) end
+function enqueue_next(song) rust_play:enqueue_next(song) end
+function dequeue_selected() rust_play:dequeue_selected() end
+function move_queued(delta) rust_play:move_queued(delta) end
+function clear_queue() rust_play:clear_queue() end
+function get_queue() return rust_play:get_queue() end
+function export_m3u(file, what) rust_play:export_m3u(file, what) end
+function set_play_mode(mode) rust_play:set_play_mode(mode) end
+function get_play_mode() return rust_play:get_play_mode() end
+function cycle_sort_order() rust_play:cycle_sort_order() end
+function set_sort_order(order) rust_play:set_sort_order(order) end
+function get_sort_order() return rust_play:get_sort_order() end
 "#;
         lua.load(prelude).exec()?;
 
@@ -190,6 +325,9 @@ function enter_or_play_selected() rust_play:enter_or_play_selected() end
             ('d', InputMode::DirScreen),
             ('s', InputMode::SearchScreen),
             ('i', InputMode::SearchInput),
+            ('q', InputMode::QueueScreen),
+            ('h', InputMode::HistoryScreen),
+            ('p', InputMode::PlaylistsScreen),
         ]
         .into();
 
@@ -238,8 +376,8 @@ function enter_or_play_selected() rust_play:enter_or_play_selected() end
                     let t = value.as_table().unwrap();
                     for item in t.sequence_values::<LuaTable>().flatten() {
                         let mut mode = item.get::<String>(1)?;
-                        mode = mode.replace("a", "nidfs");
-                        mode = mode.replace("r", "dfs");
+                        mode = mode.replace("a", "nidfsqhp");
+                        mode = mode.replace("r", "dfsqhp");
                         let key = item.get::<String>(2)?;
                         for key in key.split(',') {
                             log!("KEY {key} MODE {mode}");
@@ -381,6 +519,7 @@ function enter_or_play_selected() rust_play:enter_or_play_selected() end
 #[allow(clippy::unwrap_used)]
 mod tests {
     use std::collections::HashMap;
+    use std::path::Path;
 
     use crate::{rustplay::scripting::Scripting, value::Value};
 
@@ -410,4 +549,59 @@ return {
         let o = vars.get("b").unwrap();
         assert_eq!(o.color, Some(0xff8040));
     }
+
+    #[test]
+    fn subtune_settings_are_read() {
+        let script = r#"
+return {
+    settings = {
+        subtunes = { play_all = true }
+    }
+}
+        "#;
+        let settings = Scripting::new(script).unwrap().get_settings();
+        assert!(settings.subtunes.play_all);
+        assert!(!crate::SubtuneSettings::default().play_all);
+    }
+
+    #[test]
+    fn play_time_settings_are_read() {
+        let script = r#"
+return {
+    settings = {
+        play_time = { sid = 240 },
+        silence = { seconds = 5 },
+    }
+}
+        "#;
+        let settings = Scripting::new(script).unwrap().get_settings();
+        assert_eq!(settings.play_time(Path::new("Commando.sid")), Some(240.0));
+        assert_eq!(settings.play_time(Path::new("music.mod")), None);
+        assert_eq!(settings.silence.seconds, 5.0);
+        assert_eq!(settings.silence.level, -60.0);
+    }
+
+    #[test]
+    fn loop_settings_are_read() {
+        let script = r#"
+return {
+    settings = { loops = 3, fade_out = 4.5 }
+}
+        "#;
+        let settings = Scripting::new(script).unwrap().get_settings();
+        assert_eq!(settings.loops, 3);
+        assert_eq!(settings.fade_out, 4.5);
+    }
+
+    #[test]
+    fn crossfade_settings_are_read() {
+        let script = r#"
+return {
+    settings = { crossfade = 2.5 }
+}
+        "#;
+        let settings = Scripting::new(script).unwrap().get_settings();
+        assert_eq!(settings.crossfade, 2.5);
+        assert_eq!(settings.fade_out, 0.0);
+    }
 }
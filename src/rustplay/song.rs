@@ -49,18 +49,39 @@ impl FileInfo {
         format!("{title} / {composer}")
     }
 
+    /// Length of the song in seconds, if it was found when indexing
+    pub fn length(&self) -> Option<f64> {
+        match self.get("length") {
+            Value::Number(n) if *n > 0.0 => Some(*n),
+            _ => None,
+        }
+    }
+
     pub fn full_song_name(&self) -> String {
+        let name = self.song_name();
+        if let Some(secs) = self.length() {
+            let secs = secs.round() as u64;
+            return format!("{name} ({}:{:02})", secs / 60, secs % 60);
+        }
+        name
+    }
+
+    fn song_name(&self) -> String {
         let title = self.get_title();
         let composer = self.get("composer");
         let file_name = self.path.file_name().map(|s| s.to_string_lossy());
+        let subtune = match self.get("subtune") {
+            Value::Number(n) => format!(" #{}", *n as u32),
+            _ => String::new(),
+        };
         if composer != &Value::Unknown {
             if let Some(ext) = self.path.extension() {
-                return format!("{title} / {composer} [{}]", ext.to_string_lossy());
+                return format!("{title} / {composer}{subtune} [{}]", ext.to_string_lossy());
             }
-            return format!("{title} / {composer}");
+            return format!("{title} / {composer}{subtune}");
         }
         if let Some(file_name) = file_name {
-            return file_name.to_string();
+            return format!("{file_name}{subtune}");
         }
         "???".into()
     }
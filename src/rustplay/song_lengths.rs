@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+
+use anyhow::Result;
+use md5::{Digest, Md5};
+
+use crate::log;
+
+/// Song lengths from a HVSC `Songlengths.md5` file, keyed on the MD5 sum
+/// of the SID file.
+#[derive(Default)]
+pub struct SongLengths {
+    lengths: HashMap<u128, Vec<f64>>,
+}
+
+/// Databases found so far, per song directory
+static DATABASES: LazyLock<Mutex<HashMap<PathBuf, Option<Arc<SongLengths>>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+impl SongLengths {
+    pub fn load(file: &Path) -> Result<SongLengths> {
+        Ok(SongLengths::parse(&std::fs::read_to_string(file)?))
+    }
+
+    /// Parse the contents of a `Songlengths.md5` file. Lines look like
+    /// `5e25b3867ecd3f3443081fe04cbfcbe0=3:50 1:02.5`, one time per subtune.
+    pub fn parse(text: &str) -> SongLengths {
+        let lengths = text
+            .lines()
+            .filter_map(|line| {
+                let (md5, times) = line.trim().split_once('=')?;
+                let key = u128::from_str_radix(md5, 16).ok()?;
+                let times: Option<Vec<f64>> = times.split_whitespace().map(parse_time).collect();
+                Some((key, times?))
+            })
+            .collect();
+        SongLengths { lengths }
+    }
+
+    /// Lengths in seconds of all subtunes in the SID file `data`
+    pub fn find(&self, data: &[u8]) -> Option<&[f64]> {
+        let key = u128::from_be_bytes(Md5::digest(data).into());
+        self.lengths.get(&key).map(Vec::as_slice)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lengths.len()
+    }
+
+    /// Find the database for a song in `dir`. HVSC keeps it in
+    /// `DOCUMENTS/` at the top of the collection; otherwise we use the
+    /// one in the player data directory, if any.
+    pub fn for_dir(dir: &Path) -> Option<Arc<SongLengths>> {
+        #[allow(clippy::unwrap_used)]
+        let mut databases = DATABASES.lock().unwrap();
+        Self::lookup(&mut databases, dir)
+    }
+
+    fn lookup(
+        databases: &mut HashMap<PathBuf, Option<Arc<SongLengths>>>,
+        dir: &Path,
+    ) -> Option<Arc<SongLengths>> {
+        if let Some(found) = databases.get(dir) {
+            return found.clone();
+        }
+        let file = dir.join("DOCUMENTS").join("Songlengths.md5");
+        let found = if file.is_file() {
+            Self::load_shared(&file)
+        } else {
+            match dir.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => Self::lookup(databases, parent),
+                _ => dirs::cache_dir()
+                    .map(|d| d.join("oldplay-data").join("Songlengths.md5"))
+                    .filter(|f| f.is_file())
+                    .and_then(|f| Self::load_shared(&f)),
+            }
+        };
+        databases.insert(dir.to_owned(), found.clone());
+        found
+    }
+
+    fn load_shared(file: &Path) -> Option<Arc<SongLengths>> {
+        SongLengths::load(file)
+            .inspect(|db| log!("Loaded {} song lengths from {file:?}", db.len()))
+            .inspect_err(|e| log!("Could not read {file:?}: {e}"))
+            .ok()
+            .map(Arc::new)
+    }
+}
+
+/// Lengths of all subtunes of the SID file at `path`, and the index of the
+/// subtune that plays by default.
+pub fn sid_lengths(path: &Path) -> Option<(Vec<f64>, usize)> {
+    let dir = path.canonicalize().ok()?.parent()?.to_owned();
+    let db = SongLengths::for_dir(&dir)?;
+    let data = std::fs::read(path).ok()?;
+    let lengths = db.find(&data)?.to_vec();
+    let start = data
+        .get(0x10..0x12)
+        .map(|s| usize::from(u16::from_be_bytes([s[0], s[1]])))
+        .unwrap_or(1);
+    Some((lengths, start.saturating_sub(1)))
+}
+
+/// Parse `m:ss` or `m:ss.mmm`, ignoring any trailing attribute like `(G)`
+fn parse_time(time: &str) -> Option<f64> {
+    let time = time.split('(').next()?;
+    let (mins, secs) = time.split_once(':')?;
+    Some(mins.parse::<f64>().ok()? * 60.0 + secs.parse::<f64>().ok()?)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{SongLengths, sid_lengths};
+
+    #[test]
+    fn lengths_are_parsed() {
+        let db = SongLengths::parse(
+            "[Database]\n; /DEMOS/A.sid\nd41d8cd98f00b204e9800998ecf8427e=3:50 1:02.5 0:10(G)\n",
+        );
+        assert_eq!(db.len(), 1);
+        assert_eq!(db.find(b"").unwrap(), [230.0, 62.5, 10.0]);
+        assert!(db.find(b"x").is_none());
+    }
+
+    #[test]
+    fn hvsc_lengths_are_found() {
+        let temp = tempfile::tempdir().unwrap();
+        let hvsc = temp.path();
+        let song = hvsc.join("MUSICIANS").join("Ambient.sid");
+        std::fs::create_dir_all(song.parent().unwrap()).unwrap();
+        std::fs::create_dir_all(hvsc.join("DOCUMENTS")).unwrap();
+        std::fs::copy("music/C64/Ambient.sid", &song).unwrap();
+        std::fs::write(
+            hvsc.join("DOCUMENTS").join("Songlengths.md5"),
+            "; /MUSICIANS/Ambient.sid\n751783795b6999ab002dc2693264d42d=1:09\n",
+        )
+        .unwrap();
+        let lengths = sid_lengths(&song);
+        assert_eq!(lengths, Some((vec![69.0], 0)));
+    }
+}
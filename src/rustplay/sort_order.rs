@@ -0,0 +1,208 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use super::song::{FileInfo, FileType};
+use crate::value::Value;
+
+/// How the songs in a menu are ordered
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Score order for search results, path order for directories
+    #[default]
+    Default,
+    Title,
+    Composer,
+    Format,
+    Size,
+    Length,
+    /// Newest first
+    Added,
+    /// Most played first
+    PlayCount,
+}
+
+impl SortOrder {
+    const ALL: [SortOrder; 8] = [
+        SortOrder::Default,
+        SortOrder::Title,
+        SortOrder::Composer,
+        SortOrder::Format,
+        SortOrder::Size,
+        SortOrder::Length,
+        SortOrder::Added,
+        SortOrder::PlayCount,
+    ];
+
+    /// Name used from Lua
+    pub fn name(self) -> &'static str {
+        match self {
+            SortOrder::Default => "default",
+            SortOrder::Title => "title",
+            SortOrder::Composer => "composer",
+            SortOrder::Format => "format",
+            SortOrder::Size => "size",
+            SortOrder::Length => "length",
+            SortOrder::Added => "added",
+            SortOrder::PlayCount => "play_count",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SortOrder> {
+        SortOrder::ALL.into_iter().find(|s| s.name() == name)
+    }
+
+    /// Text for the menu header
+    pub fn label(self) -> &'static str {
+        match self {
+            SortOrder::Default => "",
+            SortOrder::Title => "by title",
+            SortOrder::Composer => "by composer",
+            SortOrder::Format => "by format",
+            SortOrder::Size => "by size",
+            SortOrder::Length => "by length",
+            SortOrder::Added => "newest first",
+            SortOrder::PlayCount => "most played",
+        }
+    }
+
+    /// The order after this one, for cycling through all orders
+    pub fn next(self) -> SortOrder {
+        let i = SortOrder::ALL.iter().position(|s| *s == self).unwrap_or(0);
+        SortOrder::ALL[(i + 1) % SortOrder::ALL.len()]
+    }
+
+    /// Positions of `songs` in this order. Directories stay first, and songs
+    /// that compare equal keep their original order.
+    pub fn sort(self, songs: &[FileInfo], play_counts: &HashMap<PathBuf, usize>) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..songs.len()).collect();
+        if self == SortOrder::Default {
+            return order;
+        }
+        order.sort_by(|&a, &b| {
+            let (a, b) = (&songs[a], &songs[b]);
+            let is_song = |s: &FileInfo| s.file_type == FileType::Song;
+            is_song(a)
+                .cmp(&is_song(b))
+                .then_with(|| self.compare(a, b, play_counts))
+        });
+        order
+    }
+
+    fn compare(
+        self,
+        a: &FileInfo,
+        b: &FileInfo,
+        play_counts: &HashMap<PathBuf, usize>,
+    ) -> Ordering {
+        let by_title = || title(a).cmp(&title(b));
+        match self {
+            SortOrder::Default => Ordering::Equal,
+            SortOrder::Title => by_title(),
+            SortOrder::Composer => text(a, "composer")
+                .cmp(&text(b, "composer"))
+                .then_with(by_title),
+            SortOrder::Format => format(a).cmp(&format(b)).then_with(by_title),
+            SortOrder::Size => compare_numbers(number(a, "size"), number(b, "size")),
+            SortOrder::Length => compare_numbers(a.length(), b.length()),
+            SortOrder::Added => compare_numbers(number(b, "added"), number(a, "added")),
+            SortOrder::PlayCount => play_counts
+                .get(&b.path)
+                .cmp(&play_counts.get(&a.path))
+                .then_with(by_title),
+        }
+    }
+}
+
+fn title(song: &FileInfo) -> String {
+    song.get_title().to_lowercase()
+}
+
+/// Lower case text, with songs missing it last
+fn text(song: &FileInfo, what: &str) -> (bool, String) {
+    match song.get(what) {
+        Value::Text(t) if !t.is_empty() => (false, t.to_lowercase()),
+        _ => (true, String::new()),
+    }
+}
+
+fn format(song: &FileInfo) -> (bool, String) {
+    let (missing, format) = text(song, "format");
+    if !missing {
+        return (missing, format);
+    }
+    match song.path.extension() {
+        Some(ext) => (false, ext.to_string_lossy().to_lowercase()),
+        None => (true, String::new()),
+    }
+}
+
+fn number(song: &FileInfo, what: &str) -> Option<f64> {
+    match song.get(what) {
+        Value::Number(n) => Some(*n),
+        _ => None,
+    }
+}
+
+/// Smallest first, with missing numbers last
+fn compare_numbers(a: Option<f64>, b: Option<f64>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use super::SortOrder;
+    use crate::rustplay::song::{FileInfo, FileType};
+    use crate::value::Value;
+
+    fn song(title: &str, size: Option<f64>) -> FileInfo {
+        let mut song = FileInfo {
+            path: PathBuf::from(format!("{title}.mod")),
+            ..FileInfo::default()
+        };
+        song.meta_data.insert("title".into(), title.into());
+        if let Some(size) = size {
+            song.meta_data.insert("size".into(), Value::Number(size));
+        }
+        song
+    }
+
+    #[test]
+    fn songs_are_sorted() {
+        let dir = FileInfo {
+            path: PathBuf::from("zdir"),
+            file_type: FileType::Dir,
+            ..FileInfo::default()
+        };
+        let songs = [
+            song("b", Some(30.0)),
+            dir,
+            song("C", None),
+            song("a", Some(10.0)),
+        ];
+        let counts = HashMap::from([(PathBuf::from("C.mod"), 3), (PathBuf::from("b.mod"), 1)]);
+        assert_eq!(SortOrder::Default.sort(&songs, &counts), [0, 1, 2, 3]);
+        assert_eq!(SortOrder::Title.sort(&songs, &counts), [1, 3, 0, 2]);
+        assert_eq!(SortOrder::Size.sort(&songs, &counts), [1, 3, 0, 2]);
+        assert_eq!(SortOrder::PlayCount.sort(&songs, &counts), [1, 2, 0, 3]);
+    }
+
+    #[test]
+    fn orders_cycle() {
+        let mut order = SortOrder::Default;
+        for _ in 0..8 {
+            assert_eq!(SortOrder::from_name(order.name()), Some(order));
+            order = order.next();
+        }
+        assert_eq!(order, SortOrder::Default);
+    }
+}
@@ -15,6 +15,9 @@ pub enum InputMode {
     FavScreen,
     DirScreen,
     SearchScreen,
+    QueueScreen,
+    HistoryScreen,
+    PlaylistsScreen,
 }
 
 pub(crate) enum Msg {
@@ -0,0 +1,114 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// How much `up()` and `down()` change the volume
+const STEP: f64 = 0.05;
+
+/// The master volume, saved between runs
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Volume {
+    /// From 0 (silent) to 1 (as loud as the song is)
+    level: f64,
+    muted: bool,
+    #[serde(skip)]
+    file: Option<PathBuf>,
+}
+
+impl Default for Volume {
+    fn default() -> Self {
+        Self {
+            level: 1.0,
+            muted: false,
+            file: None,
+        }
+    }
+}
+
+impl Volume {
+    /// Load the volume from `file`. Starts at full volume if the file is
+    /// missing or can not be read.
+    pub fn load(file: PathBuf) -> Self {
+        let volume = fs::read_to_string(&file)
+            .ok()
+            .and_then(|text| toml::from_str::<Volume>(&text).ok())
+            .unwrap_or_default();
+        Self {
+            level: volume.level.clamp(0.0, 1.0),
+            file: Some(file),
+            ..volume
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(file, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Set the volume, unmuting it, and save it
+    pub fn set(&mut self, level: f64) -> Result<()> {
+        // Rounded, so steps add up to whole percents
+        self.level = (level.clamp(0.0, 1.0) * 100.0).round() / 100.0;
+        self.muted = false;
+        self.save()
+    }
+
+    pub fn up(&mut self) -> Result<()> {
+        self.set(self.level + STEP)
+    }
+
+    pub fn down(&mut self) -> Result<()> {
+        self.set(self.level - STEP)
+    }
+
+    pub fn toggle_mute(&mut self) -> Result<()> {
+        self.muted = !self.muted;
+        self.save()
+    }
+
+    /// What to multiply the audio with
+    pub fn gain(&self) -> f64 {
+        if self.muted { 0.0 } else { self.level }
+    }
+
+    /// The volume in percent, 0 when muted
+    pub fn percent(&self) -> i32 {
+        (self.gain() * 100.0).round() as i32
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::Volume;
+
+    #[test]
+    fn volume_is_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("volume.toml");
+        let mut volume = Volume::load(file.clone());
+        assert_eq!(volume.percent(), 100);
+        volume.up().unwrap();
+        assert_eq!(volume.percent(), 100);
+        volume.down().unwrap();
+        volume.down().unwrap();
+        assert_eq!(volume.percent(), 90);
+        volume.toggle_mute().unwrap();
+        assert_eq!(volume.percent(), 0);
+
+        let mut loaded = Volume::load(file.clone());
+        // Turning the volume up or down unmutes it
+        loaded.up().unwrap();
+        assert_eq!(volume.gain(), 0.0);
+        assert_eq!(loaded.percent(), 95);
+    }
+}
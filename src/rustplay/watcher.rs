@@ -0,0 +1,170 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+/// A change to a file or directory in a watched directory
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// A file was added or written to
+    Modified(PathBuf),
+    /// A file or directory was deleted or moved away
+    Removed(PathBuf),
+    /// A new directory appeared
+    DirCreated(PathBuf),
+}
+
+impl Change {
+    pub fn path(&self) -> &Path {
+        match self {
+            Change::Modified(p) | Change::Removed(p) | Change::DirCreated(p) => p,
+        }
+    }
+}
+
+// Linux-specific implementation using inotify
+#[cfg(target_os = "linux")]
+mod linux_impl {
+    use super::*;
+    use anyhow::bail;
+    use std::collections::HashMap;
+    use std::ffi::CString;
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+    const MASK: u32 = libc::IN_CREATE
+        | libc::IN_CLOSE_WRITE
+        | libc::IN_MOVED_TO
+        | libc::IN_DELETE
+        | libc::IN_MOVED_FROM
+        | libc::IN_ONLYDIR;
+
+    /// Size of `struct inotify_event` without the name
+    const EVENT_SIZE: usize = 16;
+
+    pub struct DirWatcher {
+        fd: OwnedFd,
+        dirs: HashMap<i32, PathBuf>,
+    }
+
+    impl DirWatcher {
+        pub fn new() -> Result<DirWatcher> {
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if fd < 0 {
+                bail!("inotify_init failed: {}", io::Error::last_os_error());
+            }
+            Ok(DirWatcher {
+                fd: unsafe { OwnedFd::from_raw_fd(fd) },
+                dirs: HashMap::new(),
+            })
+        }
+
+        /// Watch `dir` (but not its sub directories) for changes
+        pub fn watch(&mut self, dir: &Path) -> Result<()> {
+            let name = CString::new(dir.as_os_str().as_bytes())?;
+            let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), name.as_ptr(), MASK) };
+            if wd < 0 {
+                bail!("Can not watch {dir:?}: {}", io::Error::last_os_error());
+            }
+            self.dirs.insert(wd, dir.to_owned());
+            Ok(())
+        }
+
+        /// All changes since the last call, without blocking
+        pub fn changes(&mut self) -> Vec<Change> {
+            let mut changes = Vec::new();
+            let mut buf = [0u8; 8192];
+            loop {
+                let n =
+                    unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+                if n <= 0 {
+                    break;
+                }
+                self.parse_events(&buf[..n as usize], &mut changes);
+            }
+            changes
+        }
+
+        fn parse_events(&mut self, mut data: &[u8], changes: &mut Vec<Change>) {
+            let field = |d: &[u8], offset: usize| {
+                u32::from_ne_bytes([d[offset], d[offset + 1], d[offset + 2], d[offset + 3]])
+            };
+            while data.len() >= EVENT_SIZE {
+                let wd = field(data, 0) as i32;
+                let mask = field(data, 4);
+                let len = field(data, 12) as usize;
+                let Some(name) = data.get(EVENT_SIZE..EVENT_SIZE + len) else {
+                    break;
+                };
+                data = &data[EVENT_SIZE + len..];
+
+                if mask & libc::IN_IGNORED != 0 {
+                    // Watched directory is gone
+                    self.dirs.remove(&wd);
+                    continue;
+                }
+                let Some(dir) = self.dirs.get(&wd) else {
+                    continue;
+                };
+                let name: Vec<u8> = name.iter().copied().take_while(|b| *b != 0).collect();
+                if name.is_empty() {
+                    continue;
+                }
+                let path = dir.join(std::ffi::OsString::from_vec(name));
+                let is_dir = mask & libc::IN_ISDIR != 0;
+                if mask & (libc::IN_DELETE | libc::IN_MOVED_FROM) != 0 {
+                    changes.push(Change::Removed(path));
+                } else if is_dir && mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+                    changes.push(Change::DirCreated(path));
+                } else if !is_dir {
+                    changes.push(Change::Modified(path));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use linux_impl::DirWatcher;
+
+/// Directories are not watched on other platforms
+#[cfg(not(target_os = "linux"))]
+pub struct DirWatcher;
+
+#[cfg(not(target_os = "linux"))]
+impl DirWatcher {
+    pub fn new() -> Result<DirWatcher> {
+        Ok(DirWatcher)
+    }
+
+    pub fn watch(&mut self, _dir: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn changes(&mut self) -> Vec<Change> {
+        Vec::new()
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{Change, DirWatcher};
+
+    #[test]
+    fn changes_are_reported() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let mut watcher = DirWatcher::new().unwrap();
+        watcher.watch(dir).unwrap();
+
+        std::fs::write(dir.join("song.mod"), b"data").unwrap();
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        std::fs::remove_file(dir.join("song.mod")).unwrap();
+        let changes = watcher.changes();
+
+        assert!(changes.contains(&Change::Modified(dir.join("song.mod"))));
+        assert!(changes.contains(&Change::DirCreated(dir.join("sub"))));
+        assert_eq!(changes.last(), Some(&Change::Removed(dir.join("song.mod"))));
+    }
+}